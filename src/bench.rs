@@ -0,0 +1,102 @@
+use std::time::{Duration, Instant};
+
+use andrgesture::{detect_spins, Point, SpinEvent};
+use gumdrop::Options;
+
+use crate::{gesture_config, Error, Opts};
+
+/// Run synthetic circular touch streams through the spin detector, checking spin counts and measuring throughput
+#[derive(Options)]
+pub struct BenchOpts {
+    help: bool,
+    /// Number of streams, alternating clockwise and counterclockwise
+    #[options(short = 'n', default = "1000")]
+    streams: u32,
+    /// Full turns per stream
+    #[options(short = 'T', default = "5")]
+    turns: u32,
+    /// Touch samples per turn
+    #[options(short = 's', default = "64")]
+    samples_per_turn: u32,
+    /// Time between samples
+    #[options(short = 'i', default = "10")]
+    interval_ms: u64,
+}
+
+/// Points circling the ring through the middle of the annulus, `turns` being negative for counterclockwise
+fn stream(opts: &Opts, bopts: &BenchOpts, turns: f32) -> Vec<(Point, Instant)> {
+    let cfg_radius = opts.radius as f32;
+    let inner = opts.inner_radius.map_or(cfg_radius / 8.0, |r| r as f32);
+    let r = (cfg_radius + inner) / 2.0;
+    let center = euclid::point2(opts.center_x, opts.center_y).to_f32();
+    // A little extra to be past the last spin despite rounding
+    let steps = ((turns.abs() + 0.05) * bopts.samples_per_turn as f32) as u32;
+    let start = Instant::now();
+    (0..=steps)
+        .map(|i| {
+            let a = turns.signum() * i as f32 / bopts.samples_per_turn as f32
+                * 2.0
+                * std::f32::consts::PI;
+            let time = start + Duration::from_millis(bopts.interval_ms * i as u64);
+            (center + euclid::vec2(a.cos(), a.sin()) * r, time)
+        })
+        .collect()
+}
+
+pub fn run(opts: &Opts, bopts: &BenchOpts) -> Result<(), Error> {
+    let cfg = gesture_config(opts)?;
+    if bopts.samples_per_turn == 0 {
+        return Err("Samples per turn must be positive".into());
+    }
+    let expected_cw = (bopts.turns as f32 / cfg.cw_spin_fraction + 0.01) as usize;
+    let expected_ccw = (bopts.turns as f32 / cfg.ccw_spin_fraction + 0.01) as usize;
+    let mut points = 0;
+    let mut failed = 0;
+    let mut elapsed = Duration::ZERO;
+    for n in 0..bopts.streams {
+        let (turns, expected) = match n % 2 {
+            0 => (bopts.turns as f32, expected_cw),
+            _ => (-(bopts.turns as f32), expected_ccw),
+        };
+        let s = stream(opts, bopts, turns);
+        let started = Instant::now();
+        let events = detect_spins(&s, &cfg);
+        elapsed += started.elapsed();
+        points += s.len();
+        let spins = events
+            .iter()
+            .filter(|e| !matches!(e, SpinEvent::Cancelled(_)))
+            .count();
+        if spins != expected || spins != events.len() {
+            failed += 1;
+            log::warn!(
+                "Stream {}: {} spins and {} cancellations, expected {} spins",
+                n,
+                spins,
+                events.len() - spins,
+                expected
+            );
+        }
+    }
+    println!(
+        "{} streams, {} points in {:.3}s: {:.0} points/s",
+        bopts.streams,
+        points,
+        elapsed.as_secs_f64(),
+        points as f64 / elapsed.as_secs_f64()
+    );
+    match failed {
+        0 => {
+            println!(
+                "PASS: {} clockwise and {} counterclockwise spins in each stream",
+                expected_cw, expected_ccw
+            );
+            Ok(())
+        }
+        _ => Err(format!(
+            "FAIL: {} of {} streams detected wrong spin counts",
+            failed, bopts.streams
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    os::unix::prelude::AsRawFd,
+    time::{Duration, Instant},
+};
+
+use gumdrop::Options;
+use nix::poll::{PollFd, PollFlags};
+
+use crate::{devices, new_tracker, poll, Error, Opts, TERMINATE};
+
+/// Print live touch coordinates and suggest center and radius from the touched area
+#[derive(Options)]
+pub struct CalibrateOpts {
+    help: bool,
+    /// How long to record the touched area
+    #[options(short = 's', default = "10")]
+    seconds: u64,
+}
+
+pub fn run(opts: &Opts, copts: &CalibrateOpts) -> Result<(), Error> {
+    let mut touch = devices::open_blocking(&opts.touchpad_file[0])?;
+    let mut tracker = new_tracker(opts);
+    println!(
+        "Draw circles over the area to use for {} seconds",
+        copts.seconds
+    );
+
+    let deadline = Instant::now() + Duration::from_secs(copts.seconds);
+    let mut min = None;
+    let mut max = None;
+    // Interrupting with Ctrl+C still prints the suggestion
+    while Instant::now() < deadline && !TERMINATE.load(std::sync::atomic::Ordering::SeqCst) {
+        let mut polls = [PollFd::new(touch.as_raw_fd(), PollFlags::POLLIN)];
+        if poll(&mut polls, 100)? == 0 {
+            continue;
+        }
+        for ev in touch.fetch_events()? {
+            if tracker.process(&ev).is_none() {
+                continue;
+            }
+            let p = match tracker.point() {
+                Some(p) => p,
+                None => continue,
+            };
+            let (lo, hi) = match (min, max) {
+                (Some(lo), Some(hi)) => (p.min(lo), p.max(hi)),
+                _ => (p, p),
+            };
+            min = Some(lo);
+            max = Some(hi);
+
+            let (center, radius) = suggest(lo, hi);
+            let d = (p - center).length();
+            let inside = d <= radius && d > radius / 8.0;
+            println!(
+                "{:5} {:5}  {}",
+                p.x,
+                p.y,
+                if inside { "inside" } else { "outside" }
+            );
+        }
+    }
+
+    match (min, max) {
+        (Some(lo), Some(hi)) => {
+            let (center, radius) = suggest(lo, hi);
+            println!(
+                "Suggested options: -x {} -y {} -r {}",
+                center.x as i32, center.y as i32, radius as i32
+            );
+        }
+        _ => println!("No touches recorded"),
+    }
+    Ok(())
+}
+
+/// Center of the touched bounding box and half of its smaller side
+fn suggest(lo: andrgesture::Point, hi: andrgesture::Point) -> (andrgesture::Point, f32) {
+    let span = hi - lo;
+    (lo.lerp(hi, 0.5), span.x.min(span.y) / 2.0)
+}
@@ -0,0 +1,876 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{ErrorKind, Write},
+    os::unix::{io::AsRawFd, process::CommandExt},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    time::{Duration, Instant, SystemTime},
+};
+
+use andrgesture::Point;
+use nix::{
+    fcntl::{FcntlArg, OFlag},
+    sys::signal::{killpg, Signal},
+    unistd::Pid,
+};
+
+use crate::{inject, Error};
+
+/// Details about a triggered gesture, made available to the command
+pub struct SpinInfo<'a> {
+    /// "cw" or "ccw", swipe direction ("up", "down", "left" or "right"), or pinch "in" or "out"
+    pub direction: &'static str,
+    /// Spin counter, negative for counterclockwise spins
+    pub spins: i32,
+    /// Accumulated angle of the gesture, in degrees
+    pub total_angle: f32,
+    pub center_x: i32,
+    pub center_y: i32,
+    /// Name of the zone, "default" unless zones are configured
+    pub zone: &'a str,
+}
+
+impl SpinInfo<'_> {
+    /// Values for `{spins}`, `{direction}`, `{total_angle}` and `{zone}` placeholders
+    fn lookup(&self, name: &str) -> Option<String> {
+        match name {
+            "spins" => Some(self.spins.to_string()),
+            "direction" => Some(self.direction.to_owned()),
+            "total_angle" => Some(format!("{:.1}", self.total_angle)),
+            "zone" => Some(self.zone.to_owned()),
+            _ => None,
+        }
+    }
+
+    fn env(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("ANDRGESTURE_DIRECTION", self.direction.to_owned()),
+            ("ANDRGESTURE_SPINS", self.spins.to_string()),
+            ("ANDRGESTURE_CENTER_X", self.center_x.to_string()),
+            ("ANDRGESTURE_CENTER_Y", self.center_y.to_string()),
+            ("ANDRGESTURE_ANGLE", format!("{:.1}", self.total_angle)),
+            ("ANDRGESTURE_ZONE", self.zone.to_owned()),
+        ]
+    }
+}
+
+/// Replace `{name}` placeholders in the command with values from `lookup`.
+/// Unknown placeholders are left as is; `{{` and `}}` produce literal braces.
+pub fn substitute(template: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            out.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let placeholder = match rest.find('}') {
+            Some(j) if j > 0 => Some(&rest[1..j]),
+            _ => None,
+        };
+        let value = placeholder.and_then(&lookup);
+        match (placeholder, value) {
+            (Some(name), Some(value)) => {
+                out.push_str(&value);
+                rest = &rest[name.len() + 2..];
+            }
+            _ => {
+                out.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Split command line into arguments for `--no-shell`. Whitespace separates arguments,
+/// except inside single quotes (taken literally) or double quotes (where `\"` and `\\` are escapes).
+/// Outside quotes, backslash escapes any character.
+pub fn split_args(cmd: &str) -> Result<Vec<String>, Error> {
+    let mut args = vec![];
+    let mut arg = String::new();
+    // Distinguishes an empty quoted argument from no argument
+    let mut in_arg = false;
+    let mut chars = cmd.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("Unterminated single quote".into()),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => return Err("Unterminated double quote".into()),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("Unterminated double quote".into()),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                arg.extend(chars.next());
+            }
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            c => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// `NAME=value` word, a variable assignment for the shell
+fn is_assignment(word: &str) -> bool {
+    let Some((name, _)) = word.split_once('=') else {
+        return false;
+    };
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Word needing no quotes, neither for the shell nor for `split_args`
+fn is_plain(word: &str) -> bool {
+    !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./+,:@%".contains(c))
+}
+
+/// Replace the program of the command line with its path in `dir` if it's a plain relative path existing there,
+/// quoted if needed. Variable assignments before the program are skipped with a `shell`.
+fn look_up(dir: &Path, cmd: &str, shell: bool) -> String {
+    let mut offset = 0;
+    for word in cmd.split_whitespace() {
+        let at = offset + cmd[offset..].find(word).unwrap_or(0);
+        offset = at + word.len();
+        if shell && is_assignment(word) {
+            continue;
+        }
+        let path = dir.join(word);
+        if !is_plain(word) || word.starts_with('/') || !path.is_file() {
+            break;
+        }
+        let path = path.to_string_lossy();
+        let program = match is_plain(&path) {
+            true => path.into_owned(),
+            false => format!("'{}'", path.replace('\'', r"'\''")),
+        };
+        return format!("{}{}{}", &cmd[..at], program, &cmd[offset..]);
+    }
+    cmd.to_owned()
+}
+
+/// Value of `--cmd-overflow`: what to do with a command while `--max-concurrent-cmds` are running
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Skip the new command
+    Drop,
+    /// Run it once another command exits
+    Queue,
+}
+
+impl std::str::FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(Overflow::Drop),
+            "queue" => Ok(Overflow::Queue),
+            _ => Err(format!("Expected `drop` or `queue`, got `{}`", s)),
+        }
+    }
+}
+
+/// Time between SIGTERM and SIGKILL for commands exceeding their timeout
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+struct Running {
+    cmd: String,
+    /// Leader of its own process group, so that the whole group can be killed
+    child: Child,
+    started: Instant,
+    /// When SIGTERM was sent
+    terminated: Option<Instant>,
+    killed: bool,
+}
+
+/// Spawns shell commands and keeps track of them until they exit
+pub struct Runner {
+    running: Vec<Running>,
+    /// Only print commands instead of running them
+    dry_run: bool,
+    /// Shell for `-c`, or `None` to execute split command lines directly
+    shell: Option<String>,
+    keys: Option<inject::KeyInjector>,
+    /// Kill commands running longer than this
+    timeout: Option<Duration>,
+    /// Command reading touch points, running while listening touchscreen
+    stream: Option<(Running, ChildStdin)>,
+    /// Stream commands terminated by `stop_stream`, kept apart from `running` so they don't count towards `limit`
+    stopped_streams: Vec<Running>,
+    /// Most commands to run at once, and what to do with more
+    limit: Option<(usize, Overflow)>,
+    /// Commands waiting for others to exit, with `Overflow::Queue`
+    queue: VecDeque<(String, Vec<(&'static str, String)>)>,
+    /// Where relative programs of command lines are looked up first
+    dir: Option<PathBuf>,
+}
+
+impl Runner {
+    pub fn new(dry_run: bool, shell: Option<String>) -> Runner {
+        Runner {
+            running: vec![],
+            dry_run,
+            shell,
+            keys: None,
+            timeout: None,
+            stream: None,
+            stopped_streams: vec![],
+            limit: None,
+            queue: VecDeque::new(),
+            dir: None,
+        }
+    }
+
+    pub fn set_dir(&mut self, dir: PathBuf) {
+        self.dir = Some(dir);
+    }
+
+    /// Command line with the program looked up in `--cmd-dir`
+    fn resolve(&self, cmd: &str) -> String {
+        match self.dir {
+            Some(ref dir) => look_up(dir, cmd, self.shell.is_some()),
+            None => cmd.to_owned(),
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = Some(timeout);
+    }
+
+    pub fn set_limit(&mut self, max: usize, overflow: Overflow) {
+        self.limit = Some((max, overflow));
+    }
+
+    /// Whether `reap` should be called soon even without input, to enforce the timeout or start queued commands
+    pub fn needs_reaping(&self) -> bool {
+        let queued = !self.queue.is_empty() && !self.running.is_empty();
+        let children = !self.running.is_empty() || !self.stopped_streams.is_empty();
+        queued || (self.timeout.is_some() && children)
+    }
+
+    /// Check that the command lines can be split into arguments without a shell, `key:` commands aside
+    pub fn check(&self, cmds: &[&String]) -> Result<(), Error> {
+        if self.shell.is_some() {
+            return Ok(());
+        }
+        for cmd in cmds {
+            if inject::parse(cmd)?.is_some() {
+                continue;
+            }
+            match split_args(cmd) {
+                Ok(args) if args.is_empty() => return Err("Empty command".into()),
+                Ok(_) => (),
+                Err(e) => return Err(format!("Invalid command `{}`: {}", cmd, e).into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Check `key:` commands among the `cmds` and create virtual keyboard for them, if there are any
+    pub fn enable_keys(&mut self, cmds: &[&String]) -> Result<(), Error> {
+        let mut keys = vec![];
+        for cmd in cmds {
+            if let Some(action) = inject::parse(cmd)? {
+                keys.push(action.key);
+            }
+        }
+        if !keys.is_empty() && !self.dry_run {
+            self.keys = Some(inject::KeyInjector::new(&keys)?);
+        }
+        Ok(())
+    }
+
+    pub fn spawn(&mut self, template: &str, info: &SpinInfo) -> Result<(), Error> {
+        if self.emit_key(template)? {
+            return Ok(());
+        }
+        let cmd = substitute(template, |name| info.lookup(name));
+        self.run(cmd, info.env())
+    }
+
+    /// Like `spawn`, but for commands not related to a gesture, without substitutions
+    pub fn spawn_plain(&mut self, cmd: &str) -> Result<(), Error> {
+        if self.emit_key(cmd)? {
+            return Ok(());
+        }
+        self.run(cmd.to_owned(), vec![])
+    }
+
+    /// Handle `key:` command, returning `false` for other commands
+    fn emit_key(&self, cmd: &str) -> Result<bool, Error> {
+        let Some(action) = inject::parse(cmd)? else {
+            return Ok(false);
+        };
+        match self.keys {
+            Some(ref keys) => keys.emit(action)?,
+            None => log::info!("would press: {:?}", action.key),
+        }
+        Ok(true)
+    }
+
+    /// Prepare command line to be run through the shell or directly
+    fn command(&self, cmd: &str) -> Result<Command, Error> {
+        let mut command = match self.shell {
+            Some(ref shell) => {
+                let mut c = Command::new(shell);
+                c.arg("-c").arg(cmd);
+                c
+            }
+            None => {
+                let args = split_args(cmd)?;
+                let (program, args) = args.split_first().ok_or("Empty command")?;
+                let mut c = Command::new(program);
+                c.args(args);
+                c
+            }
+        };
+        command.process_group(0);
+        Ok(command)
+    }
+
+    /// Run already substituted command line, unless too many commands are running already
+    pub fn run(&mut self, cmd: String, env: Vec<(&'static str, String)>) -> Result<(), Error> {
+        let cmd = self.resolve(&cmd);
+        if self.dry_run {
+            log::info!("would run: {}", cmd);
+            return Ok(());
+        }
+        match self.limit {
+            Some((max, overflow)) if self.running.len() >= max => {
+                match overflow {
+                    Overflow::Drop => log::info!("Too many commands running, dropping `{}`", cmd),
+                    Overflow::Queue => {
+                        log::debug!("Too many commands running, queueing `{}`", cmd);
+                        self.queue.push_back((cmd, env));
+                    }
+                }
+                Ok(())
+            }
+            _ => {
+                self.start(cmd, env);
+                Ok(())
+            }
+        }
+    }
+
+    /// Spawn the command, only logging failures as substitutions may still break a checked command line
+    fn start(&mut self, cmd: String, env: Vec<(&'static str, String)>) {
+        let mut command = match self.command(&cmd) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("Not running `{}`: {}", cmd, e);
+                return;
+            }
+        };
+        match command.envs(env).spawn() {
+            Ok(child) => self.running.push(Running::new(cmd, child)),
+            Err(e) => log::warn!("Failed to run `{}`: {}", cmd, e),
+        }
+    }
+
+    /// Start the command that gets `X Y TIMESTAMP` lines of touch points on stdin until `stop_stream`
+    pub fn start_stream(&mut self, cmd: &str) -> Result<(), Error> {
+        self.stop_stream();
+        let cmd = &self.resolve(cmd);
+        if self.dry_run {
+            log::info!("would stream to: {}", cmd);
+            return Ok(());
+        }
+        let mut command = match self.command(cmd) {
+            Ok(c) => c,
+            Err(e) => {
+                log::warn!("Not running `{}`: {}", cmd, e);
+                return Ok(());
+            }
+        };
+        let mut child = match command.stdin(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                log::warn!("Failed to run `{}`: {}", cmd, e);
+                return Ok(());
+            }
+        };
+        let stdin = child.stdin.take().ok_or("No stdin")?;
+        // Points are dropped rather than stalling the main loop if the command doesn't keep up
+        nix::fcntl::fcntl(stdin.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        self.stream = Some((Running::new(cmd.to_owned(), child), stdin));
+        Ok(())
+    }
+
+    pub fn stream_point(&mut self, p: Point) {
+        let Some((ref r, ref mut stdin)) = self.stream else {
+            return;
+        };
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!("{} {} {:.3}\n", p.x, p.y, time.as_secs_f64());
+        match stdin.write_all(line.as_bytes()) {
+            Ok(()) => (),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                log::debug!("Stream command `{}` is busy, dropping a point", r.cmd);
+            }
+            Err(e) => {
+                log::warn!("Failed to write to `{}`: {}", r.cmd, e);
+                self.stop_stream();
+            }
+        }
+    }
+
+    /// Close stdin of the stream command and terminate it, reaping it later
+    pub fn stop_stream(&mut self) {
+        let Some((mut r, stdin)) = self.stream.take() else {
+            return;
+        };
+        drop(stdin);
+        if let Err(e) = killpg(Pid::from_raw(r.child.id() as i32), Signal::SIGTERM) {
+            log::debug!("Failed to terminate `{}`: {}", r.cmd, e);
+        }
+        r.terminated = Some(Instant::now());
+        self.stopped_streams.push(r);
+    }
+
+    /// Collect exited children, logging unsuccessful ones, and kill the ones running too long
+    pub fn reap(&mut self) {
+        reap(&mut self.running, self.timeout);
+        reap(&mut self.stopped_streams, self.timeout);
+        while let Some((max, _)) = self.limit {
+            if self.running.len() >= max {
+                break;
+            }
+            let Some((cmd, env)) = self.queue.pop_front() else {
+                break;
+            };
+            self.start(cmd, env);
+        }
+    }
+}
+
+fn reap(children: &mut Vec<Running>, timeout: Option<Duration>) {
+    children.retain_mut(|r| match r.child.try_wait() {
+        Ok(None) => {
+            if let Some(timeout) = timeout {
+                r.enforce(timeout);
+            }
+            true
+        }
+        Ok(Some(status)) => {
+            // Signalled commands are already logged
+            if !status.success() && r.terminated.is_none() {
+                log::warn!("Command `{}` failed: {}", r.cmd, status);
+            }
+            false
+        }
+        Err(e) => {
+            log::warn!("Failed to wait for command `{}`: {}", r.cmd, e);
+            false
+        }
+    });
+}
+
+impl Running {
+    fn new(cmd: String, child: Child) -> Running {
+        Running {
+            cmd,
+            child,
+            started: Instant::now(),
+            terminated: None,
+            killed: false,
+        }
+    }
+
+    /// Send SIGTERM after `timeout`, then SIGKILL if it doesn't help
+    fn enforce(&mut self, timeout: Duration) {
+        let group = Pid::from_raw(self.child.id() as i32);
+        let signal = match self.terminated {
+            None if self.started.elapsed() > timeout => Signal::SIGTERM,
+            Some(t) if t.elapsed() > KILL_GRACE && !self.killed => Signal::SIGKILL,
+            _ => return,
+        };
+        self.killed = signal == Signal::SIGKILL;
+        log::warn!("Command `{}` timed out, sending {}", self.cmd, signal);
+        if let Err(e) = killpg(group, signal) {
+            log::warn!("Failed to kill command `{}`: {}", self.cmd, e);
+        }
+        self.terminated.get_or_insert_with(Instant::now);
+    }
+}
+
+/// Suppresses repeats of the same command within `period`
+pub struct Cooldown {
+    period: Duration,
+    last_run: HashMap<String, Instant>,
+}
+
+impl Cooldown {
+    pub fn new(period: Duration) -> Cooldown {
+        Cooldown {
+            period,
+            last_run: HashMap::new(),
+        }
+    }
+
+    /// Whether the command identified by `key` may run now. Allowed runs restart the period.
+    pub fn allow(&mut self, key: &str, now: Instant) -> bool {
+        if let Some(&t) = self.last_run.get(key) {
+            if now < t + self.period {
+                return false;
+            }
+        }
+        self.last_run.insert(key.to_owned(), now);
+        true
+    }
+}
+
+/// Command fed with a frequently changing value, run at most once per `interval`.
+/// The last value always gets through, possibly delayed.
+pub struct Throttled {
+    template: String,
+    /// Placeholder for the value
+    name: &'static str,
+    interval: Duration,
+    last_run: Option<Instant>,
+    last_value: Option<String>,
+    pending: Option<String>,
+}
+
+impl Throttled {
+    pub fn new(template: String, name: &'static str, interval: Duration) -> Throttled {
+        Throttled {
+            template,
+            name,
+            interval,
+            last_run: None,
+            last_value: None,
+            pending: None,
+        }
+    }
+
+    pub fn update(&mut self, value: String) {
+        if self.last_value.as_ref() != Some(&value) {
+            self.pending = Some(value);
+        } else {
+            self.pending = None;
+        }
+    }
+
+    /// When `poll` should be called to run the command with a pending value
+    pub fn due(&self, now: Instant) -> Option<Instant> {
+        self.pending.as_ref()?;
+        Some(self.last_run.map_or(now, |t| (t + self.interval).max(now)))
+    }
+
+    /// Run the command with the value substituted if there is a new value and the interval has passed
+    pub fn poll(&mut self, now: Instant, runner: &mut Runner) -> Result<(), Error> {
+        if self.last_run.is_some_and(|t| now < t + self.interval) {
+            return Ok(());
+        }
+        let value = match self.pending.take() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let cmd = substitute(&self.template, |name| {
+            (name == self.name).then(|| value.clone())
+        });
+        self.last_run = Some(now);
+        self.last_value = Some(value);
+        runner.run(cmd, vec![])
+    }
+}
+
+/// `--analog-cmd` value from 0 to 100, turned up by clockwise and down by counterclockwise spinning like a dimmer knob.
+/// Kept between gestures, so that each one continues from where the previous one left off.
+pub struct Analog {
+    throttled: Throttled,
+    value: f32,
+    /// Change of the value per turn
+    sensitivity: f32,
+}
+
+impl Analog {
+    pub fn new(template: String, interval: Duration, initial: f32, sensitivity: f32) -> Analog {
+        Analog {
+            throttled: Throttled::new(template, "value", interval),
+            value: initial.clamp(0.0, 100.0),
+            sensitivity,
+        }
+    }
+
+    /// Change the value by `turns` of spinning, negative for counterclockwise ones
+    pub fn turn(&mut self, turns: f32) {
+        self.value = (self.value + turns * self.sensitivity).clamp(0.0, 100.0);
+        self.throttled.update(format!("{:.0}", self.value));
+    }
+
+    pub fn due(&self, now: Instant) -> Option<Instant> {
+        self.throttled.due(now)
+    }
+
+    pub fn poll(&mut self, now: Instant, runner: &mut Runner) -> Result<(), Error> {
+        self.throttled.poll(now, runner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(cmd: &str) -> Vec<String> {
+        split_args(cmd).unwrap()
+    }
+
+    #[test]
+    fn split_on_whitespace() {
+        assert_eq!(split("torchctl  up\t1 "), ["torchctl", "up", "1"]);
+        assert!(split("   ").is_empty());
+    }
+
+    #[test]
+    fn split_quotes() {
+        assert_eq!(split(r#"echo 'a  b' "c d""#), ["echo", "a  b", "c d"]);
+        assert_eq!(split(r#"a'b c'"d e"f"#), ["ab cd ef"]);
+        // No escapes in single quotes
+        assert_eq!(split(r"echo 'a\b'"), ["echo", r"a\b"]);
+        assert_eq!(split(r#"echo "\"\\\n""#), ["echo", r#""\\n"#]);
+    }
+
+    #[test]
+    fn split_backslashes() {
+        assert_eq!(split(r"echo a\ b \'c"), ["echo", "a b", "'c"]);
+        assert_eq!(split(r"echo \\"), ["echo", r"\"]);
+    }
+
+    #[test]
+    fn split_empty_quoted_args() {
+        assert_eq!(split("echo '' \"\""), ["echo", "", ""]);
+        assert_eq!(split("''"), [""]);
+    }
+
+    #[test]
+    fn split_errors() {
+        assert!(split_args("echo 'a").is_err());
+        assert!(split_args("echo \"a").is_err());
+        assert!(split_args("echo \"a\\").is_err());
+    }
+
+    /// Directory with an executable-to-be `tool`, removed on drop
+    struct ToolDir(PathBuf);
+
+    impl ToolDir {
+        fn new(name: &str) -> ToolDir {
+            let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("tool"), "").unwrap();
+            ToolDir(dir)
+        }
+    }
+
+    impl Drop for ToolDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn look_up_relative_programs() {
+        let d = ToolDir::new("andrgesture-bin");
+        let dir = d.0.display();
+        for shell in [false, true] {
+            let tool = |cmd: &str| look_up(&d.0, cmd, shell);
+            assert_eq!(tool("tool up"), format!("{}/tool up", dir));
+            assert_eq!(tool("  ./tool up"), format!("  {}/./tool up", dir));
+            assert_eq!(tool("/usr/bin/tool up"), "/usr/bin/tool up");
+            // Not in the directory, e.g. from PATH or a shell builtin
+            assert_eq!(tool("echo tool"), "echo tool");
+            assert_eq!(tool("'tool' up"), "'tool' up");
+        }
+        assert_eq!(
+            look_up(&d.0, "A=1 tool | tool", true),
+            format!("A=1 {}/tool | tool", dir)
+        );
+        assert_eq!(look_up(&d.0, "A=1 tool", false), "A=1 tool");
+    }
+
+    #[test]
+    fn look_up_in_dir_with_spaces() {
+        let d = ToolDir::new("andrgesture bin 'x'");
+        let resolved = look_up(&d.0, "tool up", false);
+        let program = d.0.join("tool").to_string_lossy().into_owned();
+        assert_eq!(split(&resolved), [program.as_str(), "up"]);
+    }
+
+    #[test]
+    fn analog_value_turns_both_ways_from_initial() {
+        let mut a = Analog::new("dim {value}".into(), Duration::ZERO, 50.0, 25.0);
+        a.turn(-0.5);
+        assert_eq!(a.value, 37.5);
+        assert_eq!(a.throttled.pending.as_deref(), Some("38"));
+        a.turn(-10.0);
+        assert_eq!(a.value, 0.0);
+        // Spinning back up works from the bottom
+        a.turn(0.2);
+        assert_eq!(a.value, 5.0);
+        a.turn(10.0);
+        assert_eq!(a.value, 100.0);
+        a.turn(-1.0);
+        assert_eq!(a.throttled.pending.as_deref(), Some("75"));
+    }
+
+    #[test]
+    fn throttled_due_until_the_last_value_runs() {
+        let mut runner = Runner::new(true, None);
+        let mut t = Throttled::new(
+            "p {fraction}".into(),
+            "fraction",
+            Duration::from_millis(100),
+        );
+        let start = Instant::now();
+        assert_eq!(t.due(start), None);
+        t.update("0.1".into());
+        assert_eq!(t.due(start), Some(start));
+        t.poll(start, &mut runner).unwrap();
+        assert_eq!(t.due(start), None);
+        t.update("0.2".into());
+        let later = start + Duration::from_millis(30);
+        assert_eq!(t.due(later), Some(start + Duration::from_millis(100)));
+        // Too early, still pending
+        t.poll(later, &mut runner).unwrap();
+        assert!(t.due(later).is_some());
+        t.poll(start + Duration::from_millis(100), &mut runner)
+            .unwrap();
+        assert_eq!(t.due(later), None);
+    }
+
+    #[test]
+    fn stream_command_does_not_count_towards_limit() {
+        let mut runner = Runner::new(false, Some("sh".into()));
+        runner.set_limit(1, Overflow::Drop);
+        runner.start_stream("cat >/dev/null").unwrap();
+        runner.run("sleep 0.2".into(), vec![]).unwrap();
+        assert_eq!(runner.running.len(), 1);
+        runner.run("true".into(), vec![]).unwrap();
+        assert_eq!(runner.running.len(), 1, "over the limit");
+        // Still the only command counted while the stopped stream command is being reaped
+        runner.stop_stream();
+        assert_eq!(runner.running.len(), 1);
+        let start = Instant::now();
+        while !runner.running.is_empty() && start.elapsed() < Duration::from_secs(5) {
+            std::thread::sleep(Duration::from_millis(10));
+            runner.reap();
+        }
+        runner.run("true".into(), vec![]).unwrap();
+        assert_eq!(runner.running.len(), 1);
+    }
+
+    #[test]
+    fn check_without_shell() {
+        let cmds = |c: &[&str]| -> Vec<String> { c.iter().map(|s| s.to_string()).collect() };
+        let runner = Runner::new(true, None);
+        let ok = cmds(&["torchctl up", "key:KEY_A"]);
+        assert!(runner.check(&ok.iter().collect::<Vec<_>>()).is_ok());
+        for bad in ["echo 'a", "", "  "] {
+            let bad = cmds(&[bad]);
+            assert!(runner.check(&bad.iter().collect::<Vec<_>>()).is_err());
+        }
+        let shell = Runner::new(true, Some("sh".into()));
+        let unbalanced = cmds(&["echo 'a"]);
+        assert!(shell.check(&unbalanced.iter().collect::<Vec<_>>()).is_ok());
+    }
+
+    const INFO: SpinInfo = SpinInfo {
+        direction: "ccw",
+        spins: -2,
+        total_angle: -725.0,
+        center_x: 100,
+        center_y: 200,
+        zone: "volume",
+    };
+
+    fn sub(template: &str) -> String {
+        substitute(template, |name| INFO.lookup(name))
+    }
+
+    #[test]
+    fn substitute_placeholders() {
+        let cmd = "notify {zone} {direction} {spins} {total_angle}";
+        assert_eq!(sub(cmd), "notify volume ccw -2 -725.0");
+        assert_eq!(sub("{spins}{spins}"), "-2-2");
+    }
+
+    #[test]
+    fn substitute_escaped_braces() {
+        assert_eq!(sub("{{spins}} {{{spins}}}"), "{spins} {-2}");
+        assert_eq!(sub("awk '{{print $1}}'"), "awk '{print $1}'");
+    }
+
+    #[test]
+    fn substitute_keeps_unknown_and_unbalanced() {
+        assert_eq!(sub("{nope} {} {spins"), "{nope} {} {spins");
+        assert_eq!(sub("a } b {"), "a } b {");
+    }
+
+    #[test]
+    fn cooldown_per_key() {
+        let now = Instant::now();
+        let ms = Duration::from_millis;
+        let mut c = Cooldown::new(ms(500));
+        assert!(c.allow("cw", now));
+        assert!(!c.allow("cw", now + ms(499)));
+        assert!(c.allow("ccw", now + ms(100)));
+        assert!(c.allow("cw", now + ms(500)));
+        // Only allowed runs restart the period
+        assert!(!c.allow("cw", now + ms(900)));
+        assert!(c.allow("cw", now + ms(1000)));
+    }
+
+    #[test]
+    fn zero_cooldown_allows_all() {
+        let now = Instant::now();
+        let mut c = Cooldown::new(Duration::ZERO);
+        assert!(c.allow("cw", now));
+        assert!(c.allow("cw", now));
+    }
+}
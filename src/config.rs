@@ -0,0 +1,283 @@
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::Error;
+
+/// Value of a repeatable option
+#[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+#[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IntOrString {
+    Int(i64),
+    String(String),
+}
+
+/// Mirror of `Opts` for the `--config` file. Keys are the long option names with underscores.
+/// Only used to validate keys and value types and to tell boolean options apart;
+/// actual values are fed through gumdrop as arguments.
+#[allow(dead_code)]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    keybd_file: Option<OneOrMany<PathBuf>>,
+    auto_keyboard: Option<bool>,
+    touchpad_file: Option<OneOrMany<PathBuf>>,
+    auto_touch: Option<bool>,
+    relative: Option<bool>,
+    rel_start_x: Option<i32>,
+    rel_start_y: Option<i32>,
+    center_x: Option<i32>,
+    center_y: Option<i32>,
+    radius: Option<i32>,
+    rotation: Option<u32>,
+    flip_x: Option<bool>,
+    flip_y: Option<bool>,
+    center_frac: Option<String>,
+    radius_frac: Option<f32>,
+    device_ring: Option<OneOrMany<String>>,
+    inner_radius: Option<u32>,
+    radius_hysteresis: Option<u32>,
+    spin_fraction: Option<f32>,
+    cw_spin_fraction: Option<f32>,
+    ccw_spin_fraction: Option<f32>,
+    smoothing: Option<f32>,
+    min_angular_velocity: Option<f32>,
+    min_samples_per_spin: Option<u32>,
+    keep_on_reversal: Option<bool>,
+    min_quadrants: Option<u32>,
+    continuous: Option<bool>,
+    continuous_window_ms: Option<u32>,
+    warmup_ms: Option<u32>,
+    max_gesture_ms: Option<u32>,
+    angle_origin: Option<f32>,
+    reverse_cmd: Option<String>,
+    min_pressure: Option<u32>,
+    max_touch_major: Option<u32>,
+    track: Option<String>,
+    invert_direction: Option<bool>,
+    zone: Option<OneOrMany<String>>,
+    gesture_type: Option<String>,
+    swipe_distance: Option<u32>,
+    swipe_timeout_ms: Option<u32>,
+    swipe_up_cmd: Option<String>,
+    swipe_down_cmd: Option<String>,
+    swipe_left_cmd: Option<String>,
+    swipe_right_cmd: Option<String>,
+    pinch_in_cmd: Option<String>,
+    pinch_out_cmd: Option<String>,
+    pinch_ratio: Option<f32>,
+    pinch_timeout_ms: Option<u32>,
+    figure_eight_cmd: Option<String>,
+    figure_eight_fraction: Option<f32>,
+    figure_eight_timeout_ms: Option<u32>,
+    on_listen_cmd: Option<String>,
+    on_idle_cmd: Option<String>,
+    stream_cmd: Option<String>,
+    tap_cancel: Option<bool>,
+    cancel_region: Option<String>,
+    tap_max_ms: Option<u32>,
+    tap_max_movement: Option<u32>,
+    cancel_cmd: Option<String>,
+    hold_to_listen: Option<bool>,
+    hold_grace_ms: Option<u32>,
+    cw_spins_required: Option<usize>,
+    ccw_spins_required: Option<usize>,
+    after_buttonpress_attention_time_ms: Option<u32>,
+    after_spin_attention_time_ms: Option<u32>,
+    after_successful_cw_spin_sequence_attention_time: Option<u32>,
+    after_successful_ccw_spin_sequence_attention_time: Option<u32>,
+    extend_on_touch_ms: Option<u32>,
+    touch_poll_ms: Option<u32>,
+    gesture_timeout_ms: Option<u32>,
+    adaptive_timeout: Option<bool>,
+    min_gesture_timeout_ms: Option<u32>,
+    max_gesture_timeout_ms: Option<u32>,
+    /// Either a single keycode or a comma-separated string
+    keycode_to_monitor: Option<IntOrString>,
+    key_debounce_ms: Option<u32>,
+    /// Either a single keycode or a comma-separated string
+    modifier_keycodes: Option<IntOrString>,
+    disarm_keycode: Option<u16>,
+    trigger_button: Option<u16>,
+    arm_fifo: Option<PathBuf>,
+    max_jump_distance: Option<u32>,
+    max_jump_velocity: Option<u32>,
+    jump_samples: Option<u32>,
+    debug: Option<bool>,
+    /// Same as repeating `-v`
+    verbose: Option<u32>,
+    log_filter: Option<String>,
+    state_file: Option<PathBuf>,
+    stats_file: Option<PathBuf>,
+    control_socket: Option<PathBuf>,
+    dbus: Option<bool>,
+    mqtt_broker: Option<String>,
+    mqtt_topic: Option<String>,
+    haptic: Option<bool>,
+    haptic_sysfs: Option<PathBuf>,
+    haptic_device: Option<PathBuf>,
+    haptic_ms: Option<u32>,
+    haptic_sequence_ms: Option<u32>,
+    replay: Option<PathBuf>,
+    replay_fast: Option<bool>,
+    json: Option<bool>,
+    quiet: Option<bool>,
+    strict: Option<bool>,
+    grab: Option<bool>,
+    open_retries: Option<u32>,
+    open_retry_delay_ms: Option<u32>,
+    max_empty_reads: Option<u32>,
+    empty_reads_action: Option<String>,
+    cooldown_ms: Option<u32>,
+    shell: Option<String>,
+    no_shell: Option<bool>,
+    cmd_dir: Option<PathBuf>,
+    dry_run: Option<bool>,
+    cmd_timeout_ms: Option<u32>,
+    max_concurrent_cmds: Option<usize>,
+    cmd_overflow: Option<String>,
+    cmdline_for_cw_spins: Option<String>,
+    cmdline_for_ccw_spins: Option<String>,
+    per_spin_cw_cmd: Option<String>,
+    per_spin_ccw_cmd: Option<String>,
+    level_cmd: Option<String>,
+    level_base: Option<i32>,
+    level_step: Option<i32>,
+    level_max: Option<i32>,
+    cw_sequence_cmd: Option<String>,
+    ccw_sequence_cmd: Option<String>,
+    analog_cmd: Option<String>,
+    analog_sensitivity: Option<f32>,
+    analog_initial: Option<f32>,
+    analog_debounce_ms: Option<u32>,
+    scroll_wheel: Option<bool>,
+    scroll_angle: Option<f32>,
+    progress_cmd: Option<String>,
+    progress_interval_ms: Option<u32>,
+}
+
+/// Whether `key` is a boolean option, which the command line can turn off with `--no-KEY`
+fn is_flag(key: &str) -> bool {
+    toml::from_str::<FileConfig>(&format!("{} = true", key)).is_ok()
+}
+
+/// Append command line arguments to those from the config file, so that the command line overrides it.
+/// `--no-FLAG` drops `--FLAG` given before, and counting `-v` replaces `verbose` from the file instead of adding to it.
+pub fn merge_args(file: Vec<String>, cli: Vec<String>) -> Vec<String> {
+    let is_verbose = |a: &String| {
+        a == "--verbose" || (a.len() > 1 && a.starts_with('-') && a[1..].chars().all(|c| c == 'v'))
+    };
+    let mut args = file;
+    if cli.iter().any(is_verbose) {
+        args.retain(|a| a != "--verbose");
+    }
+    for arg in cli {
+        match arg.strip_prefix("--no-") {
+            Some(flag) if is_flag(&flag.replace('-', "_")) => {
+                let opt = format!("--{}", flag);
+                args.retain(|a| *a != opt);
+            }
+            _ => args.push(arg),
+        }
+    }
+    args
+}
+
+/// Read TOML config file and convert it to command line arguments,
+/// to be placed before actual command line arguments (so that the latter override it).
+pub fn load_as_args(path: &Path) -> Result<Vec<String>, Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+    let _: FileConfig = toml::from_str(&content)
+        .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+    let table: toml::Table = toml::from_str(&content)?;
+
+    let mut args = vec![];
+    for (key, value) in table {
+        let opt = format!("--{}", key.replace('_', "-"));
+        // Counting flag, repeated instead of taking a value
+        if let ("verbose", toml::Value::Integer(n)) = (key.as_str(), &value) {
+            args.extend((0..*n).map(|_| opt.clone()));
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(a) => a,
+            v => vec![v],
+        };
+        for v in values {
+            match v {
+                toml::Value::Boolean(true) => args.push(opt.clone()),
+                toml::Value::Boolean(false) => (),
+                toml::Value::String(s) => args.push(format!("{}={}", opt, s)),
+                v => args.push(format!("{}={}", opt, v)),
+            }
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use gumdrop::Options;
+
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Options from a config file with `content` and then `cli` arguments
+    fn opts(content: &str, cli: &[&str]) -> crate::Opts {
+        let path = std::env::temp_dir().join(format!("andrgesture-{}.toml", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let file = load_as_args(&path);
+        std::fs::remove_file(&path).unwrap();
+        let args = merge_args(file.unwrap(), strings(cli));
+        crate::Opts::parse_args_default(&args).unwrap()
+    }
+
+    #[test]
+    fn command_line_overrides_file() {
+        let o = opts(
+            "radius = 300\ngrab = true\ndry_run = true",
+            &["--radius", "400"],
+        );
+        assert_eq!(o.radius, 400);
+        assert!(o.grab && o.dry_run);
+    }
+
+    #[test]
+    fn command_line_turns_off_file_flags() {
+        let o = opts("grab = true\ndry_run = true", &["--no-grab"]);
+        assert!(!o.grab);
+        assert!(o.dry_run);
+        // Flags given earlier on the command line too, and flags that weren't set
+        let o = opts("", &["--grab", "--no-grab", "--no-dry-run"]);
+        assert!(!o.grab && !o.dry_run);
+    }
+
+    #[test]
+    fn command_line_verbosity_replaces_file() {
+        assert_eq!(opts("verbose = 2", &[]).verbose, 2);
+        assert_eq!(opts("verbose = 2", &["-v"]).verbose, 1);
+        assert_eq!(opts("verbose = 1", &["--verbose", "-vv"]).verbose, 3);
+    }
+
+    #[test]
+    fn no_prefix_is_kept_for_non_flags() {
+        let args = merge_args(
+            strings(&["--shell=sh"]),
+            strings(&["--no-shell", "--no-radius"]),
+        );
+        assert_eq!(args, ["--shell=sh", "--no-shell", "--no-radius"]);
+    }
+}
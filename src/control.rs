@@ -0,0 +1,236 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::unix::{
+        net::{UnixListener, UnixStream},
+        prelude::AsRawFd,
+    },
+    path::{Path, PathBuf},
+};
+
+use nix::poll::{PollFd, PollFlags};
+use serde::Serialize;
+
+use crate::{stats::Stats, zones::Zone, Error};
+
+/// Reply to `status`, see README
+#[derive(Serialize)]
+pub struct Status<'a> {
+    /// `waiting_keyboard`, `listening` or `in_gesture`
+    pub state: &'static str,
+    /// Accumulated turns of the spin gesture in progress
+    pub spinner: Option<f32>,
+    /// Time until going back to listening keyboard, `None` when not listening or while the key is held
+    pub deadline_left_ms: Option<u64>,
+    /// Arming is ignored until resumed
+    pub paused: bool,
+    pub stats: &'a Stats,
+}
+
+struct Client {
+    stream: UnixStream,
+    /// Incomplete line
+    buf: Vec<u8>,
+}
+
+/// Line-based control interface on a Unix socket, see README for commands
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+}
+
+impl ControlSocket {
+    /// Listen on `path`, replacing a stale socket file
+    pub fn bind(path: &Path) -> Result<ControlSocket, Error> {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => (),
+        }
+        let listener = UnixListener::bind(path)
+            .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+        listener.set_nonblocking(true)?;
+        log::info!("Listening for control commands on {}", path.display());
+        Ok(ControlSocket {
+            path: path.to_owned(),
+            listener,
+            clients: vec![],
+        })
+    }
+
+    /// Descriptors to wake up the main loop `poll` for
+    pub fn poll_fds(&self) -> Vec<PollFd> {
+        let mut fds = vec![PollFd::new(self.listener.as_raw_fd(), PollFlags::POLLIN)];
+        for c in &self.clients {
+            fds.push(PollFd::new(c.stream.as_raw_fd(), PollFlags::POLLIN));
+        }
+        fds
+    }
+
+    /// Accept pending connections and execute complete command lines, without blocking
+    pub fn process(&mut self, zones: &mut [Zone], paused: &mut bool, status: &Status) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    if let Err(e) = stream.set_nonblocking(true) {
+                        log::warn!("Control socket: {}", e);
+                        continue;
+                    }
+                    self.clients.push(Client {
+                        stream,
+                        buf: vec![],
+                    });
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Control socket: {}", e);
+                    break;
+                }
+            }
+        }
+        self.clients
+            .retain_mut(|c| c.process(zones, paused, status));
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl Client {
+    /// Returns `false` if the connection should be dropped
+    fn process(&mut self, zones: &mut [Zone], paused: &mut bool, status: &Status) -> bool {
+        let mut chunk = [0u8; 1024];
+        let open = loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => break false,
+                Ok(n) => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break true,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => {
+                    log::warn!("Control socket: {}", e);
+                    return false;
+                }
+            }
+        };
+        while let Some(i) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=i).collect();
+            let line = String::from_utf8_lossy(&line);
+            let reply = execute(line.trim(), zones, paused, status);
+            if let Err(e) = self.stream.write_all(reply.as_bytes()) {
+                log::warn!("Control socket: {}", e);
+                return false;
+            }
+        }
+        open
+    }
+}
+
+/// Run one command line, returning the reply
+fn execute(line: &str, zones: &mut [Zone], paused: &mut bool, status: &Status) -> String {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let result = match words[..] {
+        [] => return String::new(),
+        [cmd @ ("pause" | "resume" | "toggle")] => {
+            *paused = match cmd {
+                "pause" => true,
+                "resume" => false,
+                _ => !*paused,
+            };
+            return if *paused { "paused\n" } else { "resumed\n" }.to_owned();
+        }
+        ["status"] => {
+            return match serde_json::to_string(status) {
+                Ok(json) => json + "\n",
+                Err(e) => format!("error: {}\n", e),
+            }
+        }
+        ["params"] => Ok(()),
+        // Applied to copies first so a value rejected by one zone changes none
+        ["set", param, value] => {
+            let mut changed = zones.to_vec();
+            changed
+                .iter_mut()
+                .try_for_each(|z| z.set(param, value))
+                .map(|()| zones.clone_from_slice(&changed))
+        }
+        ["set", name, param, value] => match zones.iter_mut().find(|z| z.name == name) {
+            Some(z) => z.set(param, value),
+            None => Err(format!("unknown zone {}", name).into()),
+        },
+        _ => Err(
+            "expected `status`, `params`, `pause`, `resume`, `toggle`, `set PARAM VALUE` or `set ZONE PARAM VALUE`"
+                .into(),
+        ),
+    };
+    match result {
+        Ok(()) => {
+            if words[0] == "set" {
+                log::info!("Control command: {}", line);
+            }
+            zones.iter().map(|z| z.describe() + "\n").collect()
+        }
+        Err(e) => format!("error: {}\n", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gumdrop::Options;
+
+    use super::*;
+
+    fn zones() -> Vec<Zone> {
+        let args = [
+            "--zone",
+            "name=a;x=0;y=0;r=100",
+            "--zone",
+            "name=b;x=0;y=0;r=400",
+        ];
+        let opts = crate::Opts::parse_args_default(&args).unwrap();
+        crate::zones::build(&opts, &crate::gesture_config(&opts).unwrap()).unwrap()
+    }
+
+    fn set(zones: &mut [Zone], line: &str) -> String {
+        let stats = Stats::default();
+        let status = Status {
+            state: "waiting_keyboard",
+            spinner: None,
+            deadline_left_ms: None,
+            paused: false,
+            stats: &stats,
+        };
+        execute(line, zones, &mut false, &status)
+    }
+
+    #[test]
+    fn set_applies_to_all_zones() {
+        let mut zones = zones();
+        assert!(!set(&mut zones, "set radius 300").starts_with("error"));
+        assert!(zones.iter().all(|z| z.config.radius == 300.0));
+    }
+
+    #[test]
+    fn set_rejected_by_one_zone_changes_none() {
+        let mut zones = zones();
+        // Below the inner radius of zone b only
+        assert!(set(&mut zones, "set radius 30").starts_with("error"));
+        assert_eq!(zones[0].config.radius, 100.0);
+        assert_eq!(zones[1].config.radius, 400.0);
+    }
+
+    #[test]
+    fn set_rejects_non_finite() {
+        let mut zones = zones();
+        for line in [
+            "set radius inf",
+            "set a inner_radius NaN",
+            "set spin_fraction inf",
+        ] {
+            assert!(set(&mut zones, line).starts_with("error"), "{}", line);
+        }
+        assert_eq!(zones[0].config.radius, 100.0);
+        assert_eq!(zones[0].config.inner_radius, 12.5);
+    }
+}
@@ -0,0 +1,55 @@
+//! Gesture signals on the D-Bus session bus, available with the `dbus` cargo feature
+
+use crate::Error;
+
+#[cfg(feature = "dbus")]
+const NAME: &str = "vi.andrgesture";
+#[cfg(feature = "dbus")]
+const PATH: &str = "/vi/andrgesture";
+#[cfg(feature = "dbus")]
+const INTERFACE: &str = "vi.andrgesture.Gestures";
+
+#[cfg(feature = "dbus")]
+pub struct Bus {
+    conn: zbus::blocking::Connection,
+}
+
+#[cfg(feature = "dbus")]
+impl Bus {
+    /// Connect to the session bus and register the `vi.andrgesture` name
+    pub fn connect() -> Result<Bus, Error> {
+        let conn = zbus::blocking::Connection::session()?;
+        conn.request_name(NAME)?;
+        log::info!("Registered {} on D-Bus session bus", NAME);
+        Ok(Bus { conn })
+    }
+
+    /// Emit `SpinCompleted(direction, count)` signal
+    pub fn spin_completed(&self, direction: &str, count: i32) {
+        let ret = self.conn.emit_signal(
+            None::<zbus::names::BusName>,
+            PATH,
+            INTERFACE,
+            "SpinCompleted",
+            &(direction, count),
+        );
+        if let Err(e) = ret {
+            log::warn!("Failed to emit D-Bus signal: {}", e);
+        }
+    }
+}
+
+/// Can't be constructed without D-Bus support
+#[cfg(not(feature = "dbus"))]
+pub enum Bus {}
+
+#[cfg(not(feature = "dbus"))]
+impl Bus {
+    pub fn connect() -> Result<Bus, Error> {
+        Err("Built without D-Bus support, enable the `dbus` cargo feature".into())
+    }
+
+    pub fn spin_completed(&self, _direction: &str, _count: i32) {
+        match *self {}
+    }
+}
@@ -0,0 +1,413 @@
+use std::{
+    io::ErrorKind,
+    os::unix::prelude::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use evdev::{AbsoluteAxisType, Device, EventType, InputEvent, Key};
+use gumdrop::Options;
+use nix::{
+    fcntl::{FcntlArg, OFlag},
+    poll::PollFlags,
+};
+
+use crate::{
+    replay::{Replay, Source},
+    Error, ExitCode, TERMINATE,
+};
+
+const MIN_REOPEN_DELAY: Duration = Duration::from_millis(100);
+const MAX_REOPEN_DELAY: Duration = Duration::from_secs(10);
+
+/// Print available input devices and their capabilities
+#[derive(Options)]
+pub struct ListDevicesOpts {
+    help: bool,
+}
+
+/// Follow symlinks like `/dev/input/by-id/...` to the current `eventN` node
+fn resolve(path: &Path) -> PathBuf {
+    match std::fs::canonicalize(path) {
+        Ok(resolved) => {
+            if resolved != path {
+                log::debug!("{} is {}", path.display(), resolved.display());
+            }
+            resolved
+        }
+        Err(_) => path.to_owned(),
+    }
+}
+
+/// Open device, explaining common failures. Symlinks are resolved on each call,
+/// so `by-id` and `by-path` names keep working when the event number changes.
+pub fn open_blocking(path: &Path) -> Result<Device, Error> {
+    Device::open(resolve(path)).map_err(|e| {
+        let hint = match e.kind() {
+            ErrorKind::PermissionDenied => {
+                "; add your user to the `input` group or run with sufficient privileges"
+            }
+            ErrorKind::NotFound => "; run `andrgesture list-devices` to see available devices",
+            _ => "",
+        };
+        format!("Failed to open {}: {}{}", path.display(), e, hint).into()
+    })
+}
+
+/// Open device in non-blocking mode
+pub fn open(path: &Path) -> Result<Device, Error> {
+    let dev = open_blocking(path)?;
+    nix::fcntl::fcntl(dev.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+    Ok(dev)
+}
+
+/// Open device in non-blocking mode, retrying failures `retries` times `delay` apart (e.g. early at boot)
+pub fn open_with_retries(path: &Path, retries: u32, delay: Duration) -> Result<Device, Error> {
+    let mut attempt = 0;
+    loop {
+        match open(path) {
+            Err(e) if attempt < retries && !TERMINATE.load(Ordering::SeqCst) => {
+                attempt += 1;
+                log::warn!("Retry {}/{} in {:?}: {}", attempt, retries, delay, e);
+                crate::poll(&mut [], delay.as_millis() as _)?;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Input device, either opened or replayed from a recording
+#[allow(clippy::large_enum_variant)] // Only a few of them exist
+pub enum Input {
+    Live(Device),
+    /// Closed after failing, reopening is tried at `retry_at` with exponential backoff
+    Lost {
+        retry_at: Instant,
+        delay: Duration,
+    },
+    Replayed(Source),
+}
+
+impl Input {
+    /// Descriptor for `poll`, negative (ignored by `poll`) for lost and replayed devices
+    pub fn raw_fd(&self) -> RawFd {
+        match self {
+            Input::Live(d) => d.as_raw_fd(),
+            Input::Lost { .. } | Input::Replayed(_) => -1,
+        }
+    }
+
+    pub fn device(&self) -> Result<&Device, Error> {
+        match self {
+            Input::Live(d) => Ok(d),
+            Input::Lost { .. } => Err("Device is lost".into()),
+            Input::Replayed(_) => Err("Device properties are not available when replaying".into()),
+        }
+    }
+
+    pub fn set_grab(&mut self, grab: bool) -> std::io::Result<()> {
+        match self {
+            Input::Live(d) if grab => d.grab(),
+            Input::Live(d) => d.ungrab(),
+            Input::Lost { .. } | Input::Replayed(_) => Ok(()),
+        }
+    }
+
+    /// When the next attempt to reopen a lost device is due
+    pub fn retry_at(&self) -> Option<Instant> {
+        match *self {
+            Input::Lost { retry_at, .. } => Some(retry_at),
+            _ => None,
+        }
+    }
+
+    /// Try reopening a lost device if the attempt is due, returning whether it got reopened
+    pub fn retry(&mut self, path: &Path, now: Instant) -> bool {
+        let Input::Lost { retry_at, delay } = *self else {
+            return false;
+        };
+        if now < retry_at {
+            return false;
+        }
+        match open(path) {
+            Ok(dev) => {
+                log::info!("Reopened {} ({})", path.display(), resolve(path).display());
+                *self = Input::Live(dev);
+                true
+            }
+            Err(e) => {
+                let delay = (delay * 2).min(MAX_REOPEN_DELAY);
+                log::warn!(
+                    "Failed to reopen {}, retrying in {:?}: {}",
+                    path.display(),
+                    delay,
+                    e
+                );
+                *self = Input::Lost {
+                    retry_at: now + delay,
+                    delay,
+                };
+                false
+            }
+        }
+    }
+}
+
+pub enum ReadResult {
+    Events(Vec<InputEvent>),
+    /// Device failed and got closed until `Input::retry` reopens it, pending input is lost
+    Lost,
+}
+
+/// Value of `--empty-reads-action`: what to do with a device `poll` keeps reporting ready without events
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum EmptyReadsAction {
+    Reopen,
+    Exit,
+}
+
+impl std::str::FromStr for EmptyReadsAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reopen" => Ok(EmptyReadsAction::Reopen),
+            "exit" => Ok(EmptyReadsAction::Exit),
+            _ => Err(format!("Expected `reopen` or `exit`, got `{}`", s)),
+        }
+    }
+}
+
+/// Consecutive empty reads of a ready device, counted against `--max-empty-reads` (0 to disable)
+pub struct EmptyReads {
+    pub count: u32,
+    pub max: u32,
+    pub action: EmptyReadsAction,
+}
+
+/// Fetch events from the device if `poll` reported it ready, reporting errors as `ReadResult::Lost`
+pub fn read_ready(
+    dev: &mut Device,
+    path: &Path,
+    revents: Option<PollFlags>,
+    empty: &mut EmptyReads,
+) -> Result<ReadResult, Error> {
+    let revents = revents.unwrap_or(PollFlags::empty());
+    let failed = PollFlags::POLLERR | PollFlags::POLLHUP | PollFlags::POLLNVAL;
+    let error = if revents.intersects(failed) {
+        format!("{:?}", revents)
+    } else if !revents.intersects(PollFlags::POLLIN) {
+        return Ok(ReadResult::Events(vec![]));
+    } else {
+        let error = match dev.fetch_events() {
+            Ok(evs) => {
+                let evs: Vec<InputEvent> = evs.collect();
+                if !evs.is_empty() {
+                    empty.count = 0;
+                    return Ok(ReadResult::Events(evs));
+                }
+                None
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => return Ok(ReadResult::Events(vec![])),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => None,
+            Err(e) => Some(e.to_string()),
+        };
+        match error {
+            Some(e) => e,
+            None => {
+                empty.count += 1;
+                if empty.max == 0 || empty.count < empty.max {
+                    log::debug!("No events from {} despite poll", path.display());
+                    return Ok(ReadResult::Events(vec![]));
+                }
+                let msg = format!("{} consecutive reads without events", empty.count);
+                if empty.action == EmptyReadsAction::Exit {
+                    let error = format!("{} seems broken: {}", path.display(), msg);
+                    return Err(ExitCode::DeviceRead.wrap(error));
+                }
+                msg
+            }
+        }
+    };
+    empty.count = 0;
+    log::warn!("Error reading {}: {}", path.display(), error);
+    Ok(ReadResult::Lost)
+}
+
+/// Like `read_ready`, but also handles replayed devices. Lost devices get closed, to be reopened with `Input::retry`.
+pub fn read_input(
+    input: &mut Input,
+    path: &Path,
+    revents: Option<PollFlags>,
+    replay: &mut Option<Replay>,
+    empty: &mut EmptyReads,
+) -> Result<ReadResult, Error> {
+    let result = match input {
+        Input::Live(dev) => read_ready(dev, path, revents, empty)?,
+        Input::Lost { .. } => ReadResult::Events(vec![]),
+        Input::Replayed(source) => {
+            ReadResult::Events(replay.as_mut().map_or(vec![], |r| r.take(*source)))
+        }
+    };
+    if let ReadResult::Lost = result {
+        log::info!("Reopening {} in {:?}", path.display(), MIN_REOPEN_DELAY);
+        *input = Input::Lost {
+            retry_at: Instant::now() + MIN_REOPEN_DELAY,
+            delay: MIN_REOPEN_DELAY,
+        };
+    }
+    Ok(result)
+}
+
+/// `/dev/input/event*` devices that can be opened, ordered by event number
+pub fn enumerate() -> Vec<(PathBuf, Device)> {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir("/dev/input") {
+        Ok(dir) => dir
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| {
+                p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with("event"))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    paths.sort_by_key(|p| event_number(p));
+    paths
+        .into_iter()
+        .filter_map(|p| Device::open(&p).ok().map(|d| (p, d)))
+        .collect()
+}
+
+fn event_number(p: &Path) -> u32 {
+    p.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.trim_start_matches("event").parse().ok())
+        .unwrap_or(u32::MAX)
+}
+
+/// Minimum and maximum value of the absolute axis
+pub fn axis_range(dev: &Device, axis: AbsoluteAxisType) -> Result<(i32, i32), Error> {
+    if !dev
+        .supported_absolute_axes()
+        .is_some_and(|a| a.contains(axis))
+    {
+        return Err(format!("Device does not report {:?}", axis).into());
+    }
+    // Some drivers report only a few axes, don't trust the state array to cover every code
+    let state = dev.get_abs_state()?;
+    let Some(info) = state.get(axis.0 as usize) else {
+        return Err(format!("No state for {:?}", axis).into());
+    };
+    if info.minimum >= info.maximum {
+        return Err(format!(
+            "Invalid range {}..{} of {:?}",
+            info.minimum, info.maximum, axis
+        )
+        .into());
+    }
+    Ok((info.minimum, info.maximum))
+}
+
+pub fn is_touchscreen(dev: &Device) -> bool {
+    dev.supported_absolute_axes().is_some_and(|a| {
+        a.contains(AbsoluteAxisType::ABS_MT_POSITION_X)
+            && a.contains(AbsoluteAxisType::ABS_MT_POSITION_Y)
+    })
+}
+
+/// Reports only single-touch ABS_X/ABS_Y positions
+pub fn is_single_touch(dev: &Device) -> bool {
+    !is_touchscreen(dev)
+        && dev.supported_absolute_axes().is_some_and(|a| {
+            a.contains(AbsoluteAxisType::ABS_X) && a.contains(AbsoluteAxisType::ABS_Y)
+        })
+}
+
+/// Axes of touch positions: multitouch ones if reported, ABS_X/ABS_Y otherwise
+pub fn position_axes(dev: &Device) -> (AbsoluteAxisType, AbsoluteAxisType) {
+    match is_single_touch(dev) {
+        true => (AbsoluteAxisType::ABS_X, AbsoluteAxisType::ABS_Y),
+        false => (
+            AbsoluteAxisType::ABS_MT_POSITION_X,
+            AbsoluteAxisType::ABS_MT_POSITION_Y,
+        ),
+    }
+}
+
+pub fn has_key(dev: &Device, keycode: u16) -> bool {
+    dev.supported_events().contains(EventType::KEY)
+        && dev
+            .supported_keys()
+            .is_some_and(|k| k.contains(Key::new(keycode)))
+}
+
+pub fn find_touchscreen() -> Result<PathBuf, Error> {
+    enumerate()
+        .into_iter()
+        .find(|(_, d)| is_touchscreen(d))
+        .map(|(p, _)| p)
+        .ok_or_else(|| "No touchscreen device found".into())
+}
+
+/// First device that can emit any of the `keycodes`
+pub fn find_keyboard(keycodes: &[u16]) -> Result<PathBuf, Error> {
+    enumerate()
+        .into_iter()
+        .find(|(_, d)| keycodes.iter().any(|&k| has_key(d, k)))
+        .map(|(p, _)| p)
+        .ok_or_else(|| "No keyboard device with the monitored keycode found".into())
+}
+
+/// Print each device with capabilities relevant for this program.
+/// Devices able to emit any of `keycodes` are marked as keyboard candidates.
+pub fn list(keycodes: &[u16]) {
+    let devices = enumerate();
+    if devices.is_empty() {
+        println!("No accessible input devices found");
+    }
+    for (path, dev) in devices {
+        let events = dev.supported_events();
+        let mut caps = vec![];
+        for (t, name) in [
+            (EventType::KEY, "EV_KEY"),
+            (EventType::RELATIVE, "EV_REL"),
+            (EventType::ABSOLUTE, "EV_ABS"),
+        ] {
+            if events.contains(t) {
+                caps.push(name);
+            }
+        }
+        if let Some(axes) = dev.supported_absolute_axes() {
+            for (a, name) in [
+                (AbsoluteAxisType::ABS_X, "ABS_X"),
+                (AbsoluteAxisType::ABS_MT_POSITION_X, "ABS_MT_POSITION_X"),
+                (AbsoluteAxisType::ABS_MT_TRACKING_ID, "ABS_MT_TRACKING_ID"),
+            ] {
+                if axes.contains(a) {
+                    caps.push(name);
+                }
+            }
+        }
+        let mut marks = vec![];
+        if keycodes.iter().any(|&k| has_key(&dev, k)) {
+            marks.push("keyboard");
+        }
+        if is_touchscreen(&dev) {
+            marks.push("touchscreen");
+        }
+        println!(
+            "{}\t{}\t{}{}",
+            path.display(),
+            dev.name().unwrap_or("?"),
+            caps.join(","),
+            if marks.is_empty() {
+                String::new()
+            } else {
+                format!("\t[likely {}]", marks.join(", "))
+            }
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use serde::Serialize;
+
+use crate::summary::Summary;
+
+/// Line of the `--json` event stream. See README for the schema.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    /// Started listening touchscreen
+    Armed,
+    /// Stopped listening touchscreen
+    Idle,
+    Spin {
+        direction: &'static str,
+        /// Number of spins in this direction so far
+        count: i32,
+        /// Enough spins for the sequence command
+        completed: bool,
+        zone: &'a str,
+    },
+    Cancelled {
+        reason: &'static str,
+        zone: &'a str,
+    },
+    Swipe {
+        direction: &'static str,
+        zone: &'a str,
+    },
+    Pinch {
+        direction: &'static str,
+    },
+    FigureEight {
+        /// Direction of the first loop
+        first: &'static str,
+        zone: &'a str,
+    },
+    /// Effective configuration at startup
+    Config(Summary<'a>),
+}
+
+/// Print the event as one JSON line if `--json` is enabled
+pub fn emit(enabled: bool, event: Event) {
+    if enabled {
+        match serde_json::to_string(&event) {
+            Ok(line) => println!("{}", line),
+            Err(e) => log::error!("Failed to serialize event: {}", e),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Exit codes telling a supervisor why andrgesture stopped
+
+use crate::Error;
+
+/// Process exit status, listed in the README
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    /// Terminated by a signal, or a replay or subcommand finished
+    Clean = 0,
+    /// Any failure without a more specific code
+    Failure = 1,
+    /// Invalid options or config file
+    Usage = 2,
+    /// Input device could not be found or opened
+    DeviceOpen = 3,
+    /// Input device kept failing reads, see `--empty-reads-action exit`
+    DeviceRead = 4,
+}
+
+/// Error tagged with the code the process should exit with
+#[derive(Debug)]
+struct Tagged {
+    code: ExitCode,
+    error: Error,
+}
+
+impl std::fmt::Display for Tagged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl std::error::Error for Tagged {}
+
+impl ExitCode {
+    /// Attach this code to the error, keeping its message
+    pub fn wrap(self, error: impl Into<Error>) -> Error {
+        Box::new(Tagged {
+            code: self,
+            error: error.into(),
+        })
+    }
+
+    /// Code for an error returned from `run`, `Failure` unless it was tagged
+    pub fn of(error: &Error) -> ExitCode {
+        error
+            .downcast_ref::<Tagged>()
+            .map_or(ExitCode::Failure, |t| t.code)
+    }
+
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untagged_errors_are_failures() {
+        let e: Error = "plain".into();
+        assert_eq!(ExitCode::of(&e), ExitCode::Failure);
+    }
+
+    #[test]
+    fn wrap_keeps_code_and_message() {
+        let e = ExitCode::Usage.wrap("Pinch ratio must be greater than 1");
+        assert_eq!(ExitCode::of(&e), ExitCode::Usage);
+        assert_eq!(e.to_string(), "Pinch ratio must be greater than 1");
+        // As tagged by `map_err` in `run`
+        let e = Err::<(), Error>("No such device".into())
+            .map_err(|e| ExitCode::DeviceOpen.wrap(e))
+            .unwrap_err();
+        assert_eq!(ExitCode::of(&e), ExitCode::DeviceOpen);
+        assert_eq!(e.to_string(), "No such device");
+    }
+
+    #[test]
+    fn options_are_checked_without_devices() {
+        use gumdrop::Options;
+        let check = |args: &[&str]| crate::check_opts(&crate::Opts::parse_args_default(args)?);
+        assert!(check(&[]).is_ok());
+        for args in [
+            &["--rotation", "45"][..],
+            &["--smoothing", "1"],
+            &["--no-shell", "-c", "a'"],
+        ] {
+            assert!(check(args).is_err(), "{:?}", args);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use std::{
+    fs::File,
+    io::{ErrorKind, Read},
+    os::unix::{
+        fs::{FileTypeExt, OpenOptionsExt},
+        io::{AsRawFd, RawFd},
+    },
+    path::{Path, PathBuf},
+};
+
+use nix::{errno::Errno, fcntl::OFlag, poll::PollFlags, sys::stat::Mode};
+
+use crate::Error;
+
+/// Named pipe for `--arm-fifo`: anything written to it starts listening touchscreen
+pub struct ArmFifo {
+    path: PathBuf,
+    file: File,
+}
+
+impl ArmFifo {
+    /// Open the FIFO, creating it if it doesn't exist yet
+    pub fn open(path: &Path) -> Result<ArmFifo, Error> {
+        match nix::unistd::mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR) {
+            Ok(()) | Err(Errno::EEXIST) => (),
+            Err(e) => return Err(format!("Failed to create {}: {}", path.display(), e).into()),
+        }
+        if !std::fs::metadata(path)?.file_type().is_fifo() {
+            return Err(format!("{} is not a FIFO", path.display()).into());
+        }
+        log::info!("Listening for arming requests on {}", path.display());
+        Ok(ArmFifo {
+            path: path.to_owned(),
+            file: ArmFifo::open_file(path)?,
+        })
+    }
+
+    /// Non-blocking, so that opening doesn't wait for a writer
+    fn open_file(path: &Path) -> Result<File, Error> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        Ok(file)
+    }
+
+    pub fn raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// Read whatever got written if `poll` reported it, returning whether there was anything.
+    /// Once the last writer disconnects, the FIFO is reopened, as `poll` would keep reporting the hangup otherwise.
+    pub fn take(&mut self, revents: Option<PollFlags>) -> bool {
+        if !revents.is_some_and(|r| r.intersects(PollFlags::POLLIN | PollFlags::POLLHUP)) {
+            return false;
+        }
+        let mut buf = [0u8; 256];
+        let mut written = false;
+        loop {
+            match self.file.read(&mut buf) {
+                Ok(0) => {
+                    match ArmFifo::open_file(&self.path) {
+                        Ok(f) => self.file = f,
+                        Err(e) => log::warn!("{}", e),
+                    }
+                    break;
+                }
+                Ok(_) => written = true,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == ErrorKind::Interrupted => (),
+                Err(e) => {
+                    log::warn!("Failed to read {}: {}", self.path.display(), e);
+                    break;
+                }
+            }
+        }
+        written
+    }
+}
@@ -0,0 +1,165 @@
+//! Vibration feedback for `--haptic`, through a sysfs timed output or an input device with force feedback
+
+use std::{
+    fs::File,
+    io::Write,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use nix::libc;
+
+use crate::{devices, Error};
+
+const EV_FF: u16 = 0x15;
+const FF_RUMBLE: u16 = 0x50;
+
+nix::ioctl_read_buf!(eviocgbit_ff, b'E', 0x20 + EV_FF, u8);
+
+/// Whether the device can play rumble effects
+fn has_rumble(fd: libc::c_int) -> bool {
+    let mut bits = [0u8; (libc::FF_MAX as usize + 1) / 8];
+    let ok = unsafe { eviocgbit_ff(fd, &mut bits) }.is_ok();
+    ok && bits[FF_RUMBLE as usize / 8] & (1 << (FF_RUMBLE % 8)) != 0
+}
+
+enum Vibrator {
+    /// Writing duration in milliseconds vibrates, e.g. `/sys/class/timed_output/vibrator/enable`
+    Sysfs(PathBuf),
+    /// Input device opened for writing, with uploaded effect ids for a spin and a completed sequence
+    Rumble {
+        path: PathBuf,
+        file: File,
+        spin: i16,
+        sequence: i16,
+    },
+}
+
+pub struct Haptic {
+    vibrator: Vibrator,
+    spin: Duration,
+    sequence: Duration,
+}
+
+/// Upload a full strength rumble effect, returning its id
+fn upload(file: &File, length: Duration) -> Result<i16, Error> {
+    let mut effect: libc::ff_effect = unsafe { std::mem::zeroed() };
+    effect.type_ = FF_RUMBLE;
+    // New effect
+    effect.id = -1;
+    effect.replay.length = length.as_millis().min(u16::MAX as u128) as u16;
+    let rumble = &mut effect.u as *mut _ as *mut libc::ff_rumble_effect;
+    unsafe {
+        (*rumble).strong_magnitude = u16::MAX;
+        (*rumble).weak_magnitude = u16::MAX;
+    }
+    // EVIOCSFF writes the assigned id back
+    let request = nix::request_code_write!(b'E', 0x80, std::mem::size_of::<libc::ff_effect>());
+    let res = unsafe { libc::ioctl(file.as_raw_fd(), request as _, &mut effect) };
+    nix::errno::Errno::result(res)?;
+    Ok(effect.id)
+}
+
+impl Haptic {
+    /// Use `sysfs` if it exists, otherwise `device` or the first input device supporting FF_RUMBLE
+    pub fn open(
+        sysfs: &Path,
+        device: Option<&Path>,
+        spin: Duration,
+        sequence: Duration,
+    ) -> Result<Haptic, Error> {
+        let vibrator = match device {
+            None if sysfs.exists() => Vibrator::Sysfs(sysfs.to_owned()),
+            Some(path) => Haptic::open_rumble(path, spin, sequence)?,
+            None => {
+                let path = devices::enumerate()
+                    .into_iter()
+                    .find(|(_, d)| has_rumble(d.as_raw_fd()))
+                    .map(|(p, _)| p)
+                    .ok_or_else(|| {
+                        format!(
+                            "No vibration available for --haptic: {} doesn't exist and no input device supports FF_RUMBLE. \
+                             Use --haptic-sysfs or --haptic-device.",
+                            sysfs.display()
+                        )
+                    })?;
+                Haptic::open_rumble(&path, spin, sequence)?
+            }
+        };
+        match vibrator {
+            Vibrator::Sysfs(ref p) | Vibrator::Rumble { path: ref p, .. } => {
+                log::info!("Vibrating through {}", p.display())
+            }
+        }
+        Ok(Haptic {
+            vibrator,
+            spin,
+            sequence,
+        })
+    }
+
+    fn open_rumble(path: &Path, spin: Duration, sequence: Duration) -> Result<Vibrator, Error> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        if !has_rumble(file.as_raw_fd()) {
+            return Err(format!("{} doesn't support FF_RUMBLE", path.display()).into());
+        }
+        let upload = |length| {
+            upload(&file, length).map_err(|e| {
+                format!(
+                    "Failed to upload a vibration effect to {}: {}",
+                    path.display(),
+                    e
+                )
+            })
+        };
+        Ok(Vibrator::Rumble {
+            path: path.to_owned(),
+            spin: upload(spin)?,
+            sequence: upload(sequence)?,
+            file,
+        })
+    }
+
+    /// Short vibration on a spin, a longer one when it completes the sequence
+    pub fn buzz(&mut self, sequence_done: bool) {
+        let result = match self.vibrator {
+            Vibrator::Sysfs(ref path) => {
+                let length = match sequence_done {
+                    true => self.sequence,
+                    false => self.spin,
+                };
+                std::fs::write(path, length.as_millis().to_string())
+                    .map_err(|e| format!("{}: {}", path.display(), e))
+            }
+            Vibrator::Rumble {
+                ref path,
+                ref mut file,
+                spin,
+                sequence,
+            } => {
+                let id = if sequence_done { sequence } else { spin };
+                let play = evdev::InputEvent::new(evdev::EventType::FORCEFEEDBACK, id as u16, 1);
+                file.write_all(input_event_bytes(&play))
+                    .map_err(|e| format!("{}: {}", path.display(), e))
+            }
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to vibrate: {}", e);
+        }
+    }
+}
+
+fn input_event_bytes(ev: &evdev::InputEvent) -> &[u8] {
+    let raw: &libc::input_event = ev.as_ref();
+    unsafe {
+        std::slice::from_raw_parts(
+            raw as *const libc::input_event as *const u8,
+            std::mem::size_of::<libc::input_event>(),
+        )
+    }
+}
@@ -0,0 +1,100 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use evdev::{uinput::VirtualDevice, AttributeSet, EventType, InputEvent, Key, RelativeAxisType};
+
+use crate::Error;
+
+/// Command prefix selecting key injection instead of a shell command
+pub const KEY_PREFIX: &str = "key:";
+
+/// `key:KEY_NAME` or `key:KEY_NAME:HOLD_MS`
+#[derive(Clone, Copy)]
+pub struct KeyAction {
+    pub key: Key,
+    /// How long to keep the key pressed, zero means immediate release
+    pub hold: Duration,
+}
+
+/// Returns `None` for ordinary shell commands
+pub fn parse(cmd: &str) -> Result<Option<KeyAction>, Error> {
+    let spec = match cmd.strip_prefix(KEY_PREFIX) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let (name, hold) = match spec.split_once(':') {
+        Some((name, ms)) => (name, Duration::from_millis(ms.parse()?)),
+        None => (spec, Duration::ZERO),
+    };
+    let key = name
+        .parse()
+        .map_err(|_| format!("Unknown key name: {}", name))?;
+    Ok(Some(KeyAction { key, hold }))
+}
+
+/// Virtual uinput keyboard for emitting configured keys
+pub struct KeyInjector {
+    dev: Arc<Mutex<VirtualDevice>>,
+}
+
+impl KeyInjector {
+    pub fn new(keys: &[Key]) -> Result<KeyInjector, Error> {
+        let mut set = AttributeSet::<Key>::new();
+        for &k in keys {
+            set.insert(k);
+        }
+        let dev = evdev::uinput::VirtualDeviceBuilder::new()?
+            .name("andrgesture")
+            .with_keys(&set)?
+            .build()?;
+        Ok(KeyInjector {
+            dev: Arc::new(Mutex::new(dev)),
+        })
+    }
+
+    /// Press and release the key. Held keys get released from a separate thread.
+    pub fn emit(&self, action: KeyAction) -> Result<(), Error> {
+        let code = action.key.code();
+        let ev = move |value| InputEvent::new(EventType::KEY, code, value);
+        self.dev.lock().unwrap().emit(&[ev(1)])?;
+        if action.hold.is_zero() {
+            self.dev.lock().unwrap().emit(&[ev(0)])?;
+            return Ok(());
+        }
+        let dev = self.dev.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(action.hold);
+            if let Err(e) = dev.lock().unwrap().emit(&[ev(0)]) {
+                log::warn!("Failed to release {:?}: {}", action.key, e);
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Virtual uinput scroll wheel
+pub struct ScrollWheel {
+    dev: VirtualDevice,
+}
+
+impl ScrollWheel {
+    pub fn new() -> Result<ScrollWheel, Error> {
+        let mut axes = AttributeSet::<RelativeAxisType>::new();
+        axes.insert(RelativeAxisType::REL_WHEEL);
+        let dev = evdev::uinput::VirtualDeviceBuilder::new()?
+            .name("andrgesture wheel")
+            .with_relative_axes(&axes)?
+            .build()?;
+        Ok(ScrollWheel { dev })
+    }
+
+    /// Scroll by `detents`, positive is up
+    pub fn emit(&mut self, detents: i32) -> Result<(), Error> {
+        let code = RelativeAxisType::REL_WHEEL.0;
+        self.dev
+            .emit(&[InputEvent::new(EventType::RELATIVE, code, detents)])?;
+        Ok(())
+    }
+}
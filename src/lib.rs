@@ -0,0 +1,638 @@
+//! Spin gesture detection logic, independent from evdev devices.
+
+use std::{
+    f32::consts::PI,
+    time::{Duration, Instant},
+};
+
+use euclid::UnknownUnit;
+
+pub type Point = euclid::Point2D<f32, UnknownUnit>;
+pub type Angle = euclid::Angle<f32>;
+pub type Vector = euclid::Vector2D<f32, UnknownUnit>;
+
+/// Largest angle accepted from a single sample. Larger steps are ambiguous in direction and get clamped.
+pub const MAX_ANGLE_STEP: f32 = PI / 2.0;
+
+/// Signed angle from `from` to `to` in radians, normalized to (-π, π] and clamped to `MAX_ANGLE_STEP`
+pub fn angle_delta(from: Angle, to: Angle) -> f32 {
+    let d = (to.radians - from.radians).rem_euclid(2.0 * PI);
+    let d = if d > PI { d - 2.0 * PI } else { d };
+    d.clamp(-MAX_ANGLE_STEP, MAX_ANGLE_STEP)
+}
+
+/// Angle of `v` (from the center) in [0, 2π), clockwise with y axis pointing down, 0 being at `origin` from +x
+pub fn ring_angle(v: Vector, origin: Angle) -> Angle {
+    (v.angle_from_x_axis() - origin).positive()
+}
+
+/// Orientation correction applied to raw device coordinates
+#[derive(Clone, Copy)]
+pub struct Transform {
+    /// Clockwise rotation in degrees: 0, 90, 180 or 270
+    pub rotation: u32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Device axis ranges (min, max)
+    pub x_range: (f32, f32),
+    pub y_range: (f32, f32),
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        self.rotation == 0 && !self.flip_x && !self.flip_y
+    }
+
+    pub fn apply(&self, p: Point) -> Point {
+        let (x0, x1) = self.x_range;
+        let (y0, y1) = self.y_range;
+        // Rotation keeps coordinates within the (swapped) device ranges
+        let (mut q, xr, yr) = match self.rotation {
+            90 => (Point::new(y0 + y1 - p.y, p.x), self.y_range, self.x_range),
+            180 => (
+                Point::new(x0 + x1 - p.x, y0 + y1 - p.y),
+                self.x_range,
+                self.y_range,
+            ),
+            270 => (Point::new(p.y, x0 + x1 - p.x), self.y_range, self.x_range),
+            _ => (p, self.x_range, self.y_range),
+        };
+        if self.flip_x {
+            q.x = xr.0 + xr.1 - q.x;
+        }
+        if self.flip_y {
+            q.y = yr.0 + yr.1 - q.y;
+        }
+        q
+    }
+}
+
+/// Parameters of the ring area and gesture tracking
+#[derive(Clone)]
+pub struct GestureConfig {
+    pub center: Point,
+    pub radius: f32,
+    /// Touches closer to the center are ignored, 0 to disable
+    pub inner_radius: f32,
+    /// Once a gesture is started, the ring is widened by this much in both directions
+    pub radius_hysteresis: f32,
+    /// Reset gesture attempt if touch point jumps by more than this
+    pub max_jump_distance: f32,
+    /// Use this speed (distance per second) times the time since the previous sample as `max_jump_distance` instead
+    pub max_jump_velocity: Option<f32>,
+    /// Number of consecutive jumped samples needed to reset the gesture. Fewer are dropped as outliers.
+    pub jump_samples: u32,
+    pub gesture_timeout: Duration,
+    /// Scale `gesture_timeout` (taken as is at one turn per second) inversely with recent angular velocity,
+    /// clamped to these bounds
+    pub adaptive_timeout: Option<(Duration, Duration)>,
+    /// Part of a full turn that counts as one clockwise spin
+    pub cw_spin_fraction: f32,
+    /// Part of a full turn that counts as one counterclockwise spin
+    pub ccw_spin_fraction: f32,
+    /// Exponential moving average factor for touch coordinates in [0, 1), 0 to disable
+    pub smoothing: f32,
+    /// Slower angle changes (radians per second) don't count towards spins, 0 to disable
+    pub min_angular_velocity: f32,
+    /// Negate angle changes, so that counterclockwise motion (with y axis pointing down) counts as positive
+    pub invert_direction: bool,
+    /// Samples inside the ring needed since the previous spin for the next one to count, 0 to disable
+    pub min_samples_per_spin: u32,
+    /// Count spins in the opposite direction down instead of cancelling the gesture
+    pub keep_on_reversal: bool,
+    /// Quadrants around the center (out of 4) to visit since the previous spin for the next one to count,
+    /// 0 to disable
+    pub min_quadrants: u32,
+    /// Reset the spin counter to zero if the next spin doesn't come within this time after the previous one
+    pub continuous: Option<Duration>,
+    /// Angle changes during this time after the gesture starts don't count towards spins
+    pub warmup: Duration,
+    /// End the gesture this long after it started, however it goes on
+    pub max_duration: Option<Duration>,
+    /// Direction of angle 0 from the center, clockwise from +x. Only shifts quadrant boundaries for spin counting.
+    pub angle_origin: Angle,
+}
+
+#[derive(derive_new::new)]
+pub struct GestureState {
+    pub deadline: Instant,
+    /// Raw previous point, for jump detection
+    pub prev: Point,
+    /// Time of `prev`
+    pub prev_sample_time: Instant,
+    /// Filtered point, for angle calculation
+    pub smoothed: Point,
+    pub prev_angle: Angle,
+    pub prev_time: Instant,
+    pub start_time: Instant,
+    /// Accumulated angle, in turns. Positive is clockwise (with y axis pointing down), unless inverted.
+    #[new(default)]
+    pub spinner: f32,
+    /// Consecutive samples too far from `prev`
+    #[new(default)]
+    pub jumps: u32,
+    /// Spin count that was already reported, in turns
+    #[new(default)]
+    pub reacted_spin: f32,
+    /// Samples inside the ring since the previous reported spin
+    #[new(default)]
+    pub samples: u32,
+    /// Moving average of angular velocity, radians per second
+    #[new(default)]
+    pub speed: f32,
+    /// Bit mask of quadrants visited since the previous reported spin
+    #[new(default)]
+    pub quadrants: u8,
+    /// Time of the previous reported spin
+    #[new(default)]
+    pub reacted_at: Option<Instant>,
+}
+
+/// Bit of the quadrant the angle points to
+fn quadrant_bit(a: Angle) -> u8 {
+    let q = (a.positive().radians / (PI / 2.0)) as u32;
+    1 << q.min(3)
+}
+
+/// Shortest time between samples assumed for `GestureConfig::max_jump_velocity`,
+/// so that samples with the same timestamp aren't all jumps
+const MIN_JUMP_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Turns a spin may fall short by, so that rounding errors summed over many samples don't lose
+/// the spin of an exactly closed circle
+const SPIN_TOLERANCE: f32 = 1e-3;
+
+/// Weight of a new sample in `GestureState::speed`
+const SPEED_AVERAGING: f32 = 0.2;
+
+impl GestureState {
+    /// Time without samples inside the ring after which the gesture is reset
+    pub fn timeout(&self, cfg: &GestureConfig) -> Duration {
+        match cfg.adaptive_timeout {
+            Some((min, max)) if self.speed > 0.0 => cfg
+                .gesture_timeout
+                .mul_f32(2.0 * PI / self.speed)
+                .clamp(min, max),
+            _ => cfg.gesture_timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    Timeout,
+    Jump,
+    OppositeDirection,
+    /// Lasted longer than `GestureConfig::max_duration`
+    MaxDuration,
+}
+
+impl CancelReason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CancelReason::Timeout => "timeout",
+            CancelReason::Jump => "jump",
+            CancelReason::OppositeDirection => "opposite_direction",
+            CancelReason::MaxDuration => "max_duration",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinEvent {
+    /// One more clockwise spin, with the current spin counter
+    CwSpin(i32),
+    /// One more counterclockwise spin, with the current (negative) spin counter
+    CcwSpin(i32),
+    /// Gesture got reset
+    Cancelled(CancelReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Cw,
+    Ccw,
+}
+
+impl Direction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Direction::Cw => "cw",
+            Direction::Ccw => "ccw",
+        }
+    }
+}
+
+impl SpinEvent {
+    /// Direction and spin counter for spin events
+    pub fn spin(self) -> Option<(Direction, i32)> {
+        match self {
+            SpinEvent::CwSpin(n) => Some((Direction::Cw, n)),
+            SpinEvent::CcwSpin(n) => Some((Direction::Ccw, n)),
+            SpinEvent::Cancelled(_) => None,
+        }
+    }
+
+    /// Spin that counts the counter back towards zero, with `keep_on_reversal`
+    pub fn is_reversal(self) -> bool {
+        match self {
+            SpinEvent::CwSpin(n) => n <= 0,
+            SpinEvent::CcwSpin(n) => n >= 0,
+            SpinEvent::Cancelled(_) => false,
+        }
+    }
+}
+
+/// Run spin detection over timestamped points of a single touch, returning all events it fires
+pub fn detect_spins(points: &[(Point, Instant)], cfg: &GestureConfig) -> Vec<SpinEvent> {
+    let mut gesture = None;
+    points
+        .iter()
+        .filter_map(|&(p, t)| feed_point(&mut gesture, p, t, cfg))
+        .collect()
+}
+
+/// Parameters of straight swipe detection. The swipe has to start inside the ring of `GestureConfig`.
+pub struct SwipeConfig {
+    /// Net displacement from the initial touch point needed to trigger
+    pub distance: f32,
+    /// Swipe has to reach `distance` within this time, otherwise it starts over from the current point
+    pub timeout: Duration,
+}
+
+pub struct SwipeState {
+    pub start: Point,
+    pub start_time: Instant,
+    /// Only one swipe is reported per touch
+    pub fired: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwipeDirection {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl SwipeDirection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SwipeDirection::Up => "up",
+            SwipeDirection::Down => "down",
+            SwipeDirection::Left => "left",
+            SwipeDirection::Right => "right",
+        }
+    }
+}
+
+/// Process one touch point for swipe detection. State in `swipe` is kept until the finger is lifted.
+pub fn feed_swipe(
+    swipe: &mut Option<SwipeState>,
+    p: Point,
+    now: Instant,
+    area: &GestureConfig,
+    cfg: &SwipeConfig,
+) -> Option<SwipeDirection> {
+    let s = match swipe {
+        Some(s) => s,
+        None => {
+            if (p - area.center).square_length() <= area.radius * area.radius {
+                *swipe = Some(SwipeState {
+                    start: p,
+                    start_time: now,
+                    fired: false,
+                });
+            }
+            return None;
+        }
+    };
+    if s.fired {
+        return None;
+    }
+    if now.saturating_duration_since(s.start_time) > cfg.timeout {
+        s.start = p;
+        s.start_time = now;
+        return None;
+    }
+    let v = p - s.start;
+    if v.square_length() < cfg.distance * cfg.distance {
+        return None;
+    }
+    s.fired = true;
+    // y axis points down
+    Some(match (v.x.abs() > v.y.abs(), v.x > 0.0, v.y > 0.0) {
+        (true, true, _) => SwipeDirection::Right,
+        (true, false, _) => SwipeDirection::Left,
+        (false, _, true) => SwipeDirection::Down,
+        (false, _, false) => SwipeDirection::Up,
+    })
+}
+
+/// Parameters of figure-eight detection: a partial turn one way, then back the other way
+pub struct FigureEightConfig {
+    /// Part of a full turn each of the two loops has to cover
+    pub fraction: f32,
+    /// Both loops have to be done within this time, otherwise it starts over from the current point
+    pub timeout: Duration,
+}
+
+pub struct FigureEightState {
+    /// Spinner value the current loop started from
+    pub start: f32,
+    pub start_time: Instant,
+    /// Direction of the completed first loop
+    pub first: Option<Direction>,
+}
+
+/// Process the spinner value of a spin gesture in progress. Returns the direction of the first loop
+/// once the second one is completed.
+pub fn feed_figure_eight(
+    fig: &mut Option<FigureEightState>,
+    spinner: f32,
+    now: Instant,
+    cfg: &FigureEightConfig,
+) -> Option<Direction> {
+    let restart = FigureEightState {
+        start: spinner,
+        start_time: now,
+        first: None,
+    };
+    let s = match fig {
+        Some(s) if now.saturating_duration_since(s.start_time) <= cfg.timeout => s,
+        _ => {
+            *fig = Some(restart);
+            return None;
+        }
+    };
+    let d = spinner - s.start;
+    match s.first {
+        None => {
+            if d.abs() >= cfg.fraction {
+                s.first = Some(if d > 0.0 {
+                    Direction::Cw
+                } else {
+                    Direction::Ccw
+                });
+                s.start = spinner;
+            }
+        }
+        Some(first) => {
+            let forward = match first {
+                Direction::Cw => d,
+                Direction::Ccw => -d,
+            };
+            if forward > 0.0 {
+                // Still in the first loop, the second one starts at its far end
+                s.start = spinner;
+            } else if -forward >= cfg.fraction {
+                *fig = None;
+                return Some(first);
+            }
+        }
+    }
+    None
+}
+
+/// Parameters of two-finger pinch detection
+pub struct PinchConfig {
+    /// Change of distance between fingers needed to trigger, e.g. 1.5 for 50% wider or narrower
+    pub ratio: f32,
+    /// Pinch has to reach `ratio` within this time, otherwise it starts over from the current distance
+    pub timeout: Duration,
+}
+
+pub struct PinchState {
+    pub start_distance: f32,
+    pub start_time: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinchEvent {
+    /// Fingers got closer
+    In,
+    /// Fingers got further apart
+    Out,
+    /// One of the fingers got lifted before reaching the ratio
+    Cancelled,
+}
+
+/// Process positions of two fingers, `None` if there are fewer fingers.
+/// After triggering, the pinch continues from the current distance.
+pub fn feed_pinch(
+    pinch: &mut Option<PinchState>,
+    pair: Option<(Point, Point)>,
+    now: Instant,
+    cfg: &PinchConfig,
+) -> Option<PinchEvent> {
+    let Some((a, b)) = pair else {
+        return pinch.take().map(|_| PinchEvent::Cancelled);
+    };
+    let distance = (a - b).length();
+    let s = pinch.get_or_insert(PinchState {
+        start_distance: distance,
+        start_time: now,
+    });
+    let event = if now.saturating_duration_since(s.start_time) > cfg.timeout {
+        None
+    } else if distance >= s.start_distance * cfg.ratio {
+        Some(PinchEvent::Out)
+    } else if distance * cfg.ratio <= s.start_distance {
+        Some(PinchEvent::In)
+    } else {
+        return None;
+    };
+    s.start_distance = distance;
+    s.start_time = now;
+    event
+}
+
+/// Parameters of tap detection: short touch without much movement
+pub struct TapConfig {
+    pub max_duration: Duration,
+    pub max_movement: f32,
+}
+
+pub struct TapState {
+    pub start: Point,
+    pub start_time: Instant,
+    /// Touch moved too far to be a tap
+    pub moved: bool,
+}
+
+/// Track a touch point for tap detection
+pub fn feed_tap(tap: &mut Option<TapState>, p: Point, now: Instant, cfg: &TapConfig) {
+    let t = tap.get_or_insert(TapState {
+        start: p,
+        start_time: now,
+        moved: false,
+    });
+    if (p - t.start).square_length() > cfg.max_movement * cfg.max_movement {
+        t.moved = true;
+    }
+}
+
+/// Finger got lifted. Returns the touch position if it was a tap.
+pub fn finish_tap(tap: &mut Option<TapState>, now: Instant, cfg: &TapConfig) -> Option<Point> {
+    let t = tap.take()?;
+    let quick = now.saturating_duration_since(t.start_time) <= cfg.max_duration;
+    (quick && !t.moved).then_some(t.start)
+}
+
+/// Process one touch point. Starts, continues or removes a gesture in `gesture`.
+pub fn feed_point(
+    gesture: &mut Option<GestureState>,
+    p: Point,
+    now: Instant,
+    cfg: &GestureConfig,
+) -> Option<SpinEvent> {
+    let (radius, inner_radius) = match gesture {
+        Some(_) => (
+            cfg.radius + cfg.radius_hysteresis,
+            (cfg.inner_radius - cfg.radius_hysteresis).max(0.0),
+        ),
+        None => (cfg.radius, cfg.inner_radius),
+    };
+    let sqradius = radius * radius;
+    let sqinner = inner_radius * inner_radius;
+
+    let smoothed = match gesture {
+        Some(g) => g.smoothed.lerp(p, 1.0 - cfg.smoothing),
+        None => p,
+    };
+    let v = smoothed - cfg.center;
+    let inside_area =
+        v.square_length() <= sqradius && (inner_radius <= 0.0 || v.square_length() > sqinner);
+
+    if inside_area && gesture.is_none() {
+        let a = ring_angle(v, cfg.angle_origin);
+        *gesture = Some(GestureState::new(
+            now + cfg.gesture_timeout,
+            p,
+            now,
+            p,
+            a,
+            now,
+            now,
+        ));
+    }
+
+    let g = gesture.as_mut()?;
+
+    let maxd = match cfg.max_jump_velocity {
+        Some(v) => {
+            let dt = now.saturating_duration_since(g.prev_sample_time);
+            v * dt.max(MIN_JUMP_INTERVAL).as_secs_f32()
+        }
+        None => cfg.max_jump_distance,
+    };
+    let mut cancel = None;
+    if now > g.deadline {
+        cancel = Some(CancelReason::Timeout);
+    } else if cfg
+        .max_duration
+        .is_some_and(|m| now.saturating_duration_since(g.start_time) > m)
+    {
+        cancel = Some(CancelReason::MaxDuration);
+    } else if (p - g.prev).square_length() > maxd * maxd {
+        g.jumps += 1;
+        if g.jumps < cfg.jump_samples {
+            return None;
+        }
+        cancel = Some(CancelReason::Jump);
+    } else {
+        g.jumps = 0;
+    }
+    if let Some(reason) = cancel {
+        *gesture = None;
+        return Some(SpinEvent::Cancelled(reason));
+    }
+    g.smoothed = smoothed;
+
+    let mut event = None;
+    if inside_area {
+        let a = ring_angle(v, cfg.angle_origin);
+        let mut d = angle_delta(g.prev_angle, a);
+        if cfg.invert_direction {
+            d = -d;
+        }
+        let dt = now.saturating_duration_since(g.prev_time).as_secs_f32();
+        let warming_up = now.saturating_duration_since(g.start_time) < cfg.warmup;
+        if !warming_up && (dt <= 0.0 || d.abs() / dt >= cfg.min_angular_velocity) {
+            g.spinner += d / PI / 2.0;
+        }
+        if dt > 0.0 {
+            g.speed += (d.abs() / dt - g.speed) * SPEED_AVERAGING;
+        }
+        g.deadline = now + g.timeout(cfg);
+        g.prev_time = now;
+        g.samples += 1;
+        g.quadrants |= quadrant_bit(a);
+        if let (Some(window), Some(t)) = (cfg.continuous, g.reacted_at) {
+            if now.saturating_duration_since(t) > window {
+                // Paused, keep only the progress towards the next spin
+                g.spinner -= g.reacted_spin;
+                g.reacted_spin = 0.0;
+                g.reacted_at = None;
+            }
+        }
+
+        let (fcw, fccw) = (cfg.cw_spin_fraction, cfg.ccw_spin_fraction);
+        let on_cw_side = g.reacted_spin > fcw / 2.0;
+        let on_ccw_side = g.reacted_spin < -fccw / 2.0;
+        // Counting back towards zero goes in steps of that side's direction
+        let cw_step = if on_ccw_side { fccw } else { fcw };
+        let ccw_step = if on_cw_side { fcw } else { fccw };
+        let mut react_cw = match on_ccw_side {
+            true => g.spinner > g.reacted_spin + cw_step - SPIN_TOLERANCE,
+            false => g.spinner >= g.reacted_spin + cw_step - SPIN_TOLERANCE,
+        };
+        let mut react_ccw = match on_ccw_side {
+            true => g.spinner <= g.reacted_spin - ccw_step + SPIN_TOLERANCE,
+            false => g.spinner < g.reacted_spin - ccw_step + SPIN_TOLERANCE,
+        };
+        let reversal = (react_cw && on_ccw_side) || (react_ccw && on_cw_side);
+        if reversal && !cfg.keep_on_reversal {
+            cancel = Some(CancelReason::OppositeDirection);
+            react_cw = false;
+            react_ccw = false;
+        }
+        // Too fast to be deliberate or not really around the center, wait for more samples
+        if g.samples < cfg.min_samples_per_spin || g.quadrants.count_ones() < cfg.min_quadrants {
+            react_cw = false;
+            react_ccw = false;
+        }
+        if react_cw {
+            g.reacted_spin += cw_step;
+        }
+        if react_ccw {
+            g.reacted_spin -= ccw_step;
+        }
+        if react_cw || react_ccw {
+            g.reacted_at = Some(now);
+            g.samples = 0;
+            g.quadrants = quadrant_bit(a);
+        }
+
+        let ctr: i32 = match g.reacted_spin > 0.0 {
+            true => (g.reacted_spin / fcw).round() as i32,
+            false => (g.reacted_spin / fccw).round() as i32,
+        };
+        if react_cw {
+            event = Some(SpinEvent::CwSpin(ctr));
+        }
+        if react_ccw {
+            event = Some(SpinEvent::CcwSpin(ctr));
+        }
+
+        g.prev_angle = a;
+    }
+    g.prev = p;
+    g.prev_sample_time = now;
+
+    if let Some(reason) = cancel {
+        *gesture = None;
+        return Some(SpinEvent::Cancelled(reason));
+    }
+    event
+}
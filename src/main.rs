@@ -1,19 +1,50 @@
 use std::{
-    f32::consts::PI,
-    os::unix::prelude::AsRawFd,
+    collections::HashSet,
+    io::IsTerminal,
     path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
     time::{Duration, Instant, SystemTime},
 };
 
-use euclid::{point2, UnknownUnit};
-use evdev::Device;
+use andrgesture::{
+    feed_figure_eight, feed_pinch, feed_point, feed_swipe, feed_tap, finish_tap, Angle,
+    CancelReason, FigureEightConfig, FigureEightState, GestureConfig, GestureState, PinchConfig,
+    PinchEvent, PinchState, Point, SpinEvent, SwipeConfig, SwipeDirection, SwipeState, TapConfig,
+    TapState, Transform,
+};
+use euclid::point2;
 
+use devices::Input;
+use exit::ExitCode;
 use gumdrop::Options;
 use nix::{
-    fcntl::{FcntlArg, OFlag},
+    errno::Errno,
     poll::{PollFd, PollFlags},
+    sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal},
 };
 
+mod bench;
+mod calibrate;
+mod commands;
+mod config;
+mod control;
+mod dbus;
+mod devices;
+mod events;
+mod exit;
+mod fifo;
+mod haptic;
+mod inject;
+mod monitor;
+mod mqtt;
+mod replay;
+mod state;
+mod stats;
+mod summary;
+mod systemd;
+mod touch;
+mod zones;
+
 /// Monitor keyboard evdev devide. When a particular key is pressed, start monitoring touchpad/touchscreen
 /// for specific gesture (clockwise or counterclockwise spins around a specific point), issuing commands
 /// if enough spins are attained
@@ -21,16 +52,192 @@ use nix::{
 #[derive(Options)]
 struct Opts {
     help: bool,
-    #[options(short = 'k', default = "/dev/input/event0")]
-    keybd_file: PathBuf,
-    #[options(short = 't', default = "/dev/input/event2")]
-    touchpad_file: PathBuf,
+    #[options(command)]
+    command: Option<Command>,
+    /// Load settings from this TOML file. Keys are long option names with underscores.
+    /// Command line options override values from the file.
+    #[options(no_short)]
+    config: Option<PathBuf>,
+    /// Keyboard device to monitor, may be specified multiple times (default: /dev/input/event0)
+    #[options(short = 'k')]
+    keybd_file: Vec<PathBuf>,
+    /// Find keyboard device reporting the monitored keycode instead of using `-k`
+    #[options(no_short)]
+    auto_keyboard: bool,
+    /// Touch device to monitor, may be specified multiple times (default: /dev/input/event2)
+    #[options(short = 't')]
+    touchpad_file: Vec<PathBuf>,
+    /// Find multitouch device instead of using `-t`
+    #[options(no_short)]
+    auto_touch: bool,
+    /// Touch devices report relative motion (REL_X/REL_Y), accumulate it into a virtual cursor
+    #[options(no_short)]
+    relative: bool,
+    /// Virtual cursor position when relative touch contact starts (default: center)
+    #[options(no_short)]
+    rel_start_x: Option<i32>,
+    /// Virtual cursor position when relative touch contact starts (default: center)
+    #[options(no_short)]
+    rel_start_y: Option<i32>,
     #[options(short = 'x', default = "1126")]
     center_x: i32,
     #[options(short = 'y', default = "748")]
     center_y: i32,
     #[options(short = 'r', default = "500")]
     radius: i32,
+    /// Rotate touch coordinates clockwise by 0, 90, 180 or 270 degrees. Center is specified after the rotation.
+    #[options(no_short, default = "0")]
+    rotation: u32,
+    /// Mirror touch coordinates horizontally (after rotation)
+    #[options(no_short)]
+    flip_x: bool,
+    /// Mirror touch coordinates vertically (after rotation)
+    #[options(no_short)]
+    flip_y: bool,
+    /// Center as fractions of the touch device axis ranges, e.g. `0.5,0.5`. Overrides `-x` and `-y`.
+    #[options(no_short)]
+    center_frac: Option<FracPair>,
+    /// Radius as a fraction of the smaller touch device axis range. Overrides `-r`.
+    #[options(no_short)]
+    radius_frac: Option<f32>,
+    /// Ring `PATH=X,Y,R` of the touch device given with `-t` as PATH, mapped onto `-x -y -r`, for panels with other coordinates. Repeatable.
+    #[options(no_short, meta = "PATH=X,Y,R")]
+    device_ring: Vec<DeviceRing>,
+    /// Ignore touches closer than this to the center, 0 to disable (default: radius/8)
+    #[options(no_short)]
+    inner_radius: Option<u32>,
+    /// Part of a full turn that counts as one spin, e.g. 0.25 for quarter-turns
+    #[options(no_short, default = "1.0")]
+    spin_fraction: f32,
+    /// Part of a full turn that counts as one clockwise spin (default: `--spin-fraction`)
+    #[options(no_short)]
+    cw_spin_fraction: Option<f32>,
+    /// Part of a full turn that counts as one counterclockwise spin (default: `--spin-fraction`)
+    #[options(no_short)]
+    ccw_spin_fraction: Option<f32>,
+    /// Smoothing factor for touch coordinates, from 0 (disabled) to below 1
+    #[options(no_short, default = "0")]
+    smoothing: f32,
+    /// Ignore slower angle changes (radians per second) as drift
+    #[options(no_short, default = "0")]
+    min_angular_velocity: f32,
+    /// Point of multitouch contacts to track: `first` finger or `centroid` of all fingers
+    #[options(no_short, default = "first", meta = "first|centroid")]
+    track: touch::TrackMode,
+    /// Ignore contacts reporting lower ABS_MT_PRESSURE, no effect on devices without pressure
+    #[options(no_short, default = "0")]
+    min_pressure: u32,
+    /// Ignore contacts reporting larger ABS_MT_TOUCH_MAJOR (palms), 0 to disable
+    #[options(no_short, default = "0")]
+    max_touch_major: u32,
+    /// Don't count a spin made of fewer touch samples than this, to filter out fast flicks
+    #[options(no_short, default = "0")]
+    min_samples_per_spin: u32,
+    /// Count spins in the opposite direction down instead of cancelling the gesture
+    #[options(no_short)]
+    keep_on_reversal: bool,
+    /// Quadrants around the center (1-4) the touch has to pass through for a spin to count
+    #[options(no_short, default = "0")]
+    min_quadrants: u32,
+    /// Reset the spin count if the next spin doesn't come within `--continuous-window-ms`, so that `-F`/`-R` need one sustained motion
+    #[options(no_short)]
+    continuous: bool,
+    /// Longest time between spins that keeps counting with `--continuous`
+    #[options(no_short, default = "1500")]
+    continuous_window_ms: u32,
+    /// Don't count rotation during this time after a gesture starts, to skip a jerky start
+    #[options(no_short, default = "0")]
+    warmup_ms: u32,
+    /// End a spin gesture this long after it started even if it goes on; the next one needs lifting the finger
+    #[options(no_short, meta = "MS")]
+    max_gesture_ms: Option<u32>,
+    /// Direction of angle 0, in degrees clockwise from the right (+x), e.g. 270 for 12 o'clock
+    #[options(no_short, default = "0", meta = "DEGREES")]
+    angle_origin: f32,
+    /// Command to run when spinning direction reverses
+    #[options(no_short)]
+    reverse_cmd: Option<String>,
+    /// Swap clockwise and counterclockwise. By default clockwise is as seen with y axis pointing down.
+    #[options(no_short)]
+    invert_direction: bool,
+    /// Widen the ring by this much while a gesture is in progress, to avoid flicker on the edges
+    #[options(no_short, default = "0")]
+    radius_hysteresis: u32,
+    /// Named zone `name=NAME;x=X;y=Y;r=R[;cw=CMD][;ccw=CMD][;cw_spins=N][;ccw_spins=N]`, repeatable. Replaces `-x -y -r -c -C`.
+    #[options(no_short, meta = "SPEC")]
+    zone: Vec<zones::ZoneSpec>,
+    /// Gestures to detect: spin, swipe or both
+    #[options(no_short, default = "spin")]
+    gesture_type: GestureType,
+    /// Distance a swipe has to cover from the initial touch point
+    #[options(no_short, default = "300")]
+    swipe_distance: u32,
+    /// Time limit for a swipe to cover `--swipe-distance`
+    #[options(no_short, default = "500")]
+    swipe_timeout_ms: u32,
+    /// Command to run on a swipe up. Same substitutions as for `-c`, `{direction}` being `up`.
+    #[options(no_short)]
+    swipe_up_cmd: Option<String>,
+    /// Command to run on a swipe down
+    #[options(no_short)]
+    swipe_down_cmd: Option<String>,
+    /// Command to run on a swipe left
+    #[options(no_short)]
+    swipe_left_cmd: Option<String>,
+    /// Command to run on a swipe right
+    #[options(no_short)]
+    swipe_right_cmd: Option<String>,
+    /// Command to run when two fingers get closer by `--pinch-ratio`. Disables spins and swipes while two fingers are down.
+    #[options(no_short)]
+    pinch_in_cmd: Option<String>,
+    /// Command to run when two fingers get further apart by `--pinch-ratio`
+    #[options(no_short)]
+    pinch_out_cmd: Option<String>,
+    /// Change of distance between two fingers that counts as a pinch
+    #[options(no_short, default = "1.5")]
+    pinch_ratio: f32,
+    /// Time limit for a pinch to reach `--pinch-ratio`
+    #[options(no_short, default = "1000")]
+    pinch_timeout_ms: u32,
+    /// Command to run on a figure-eight: `--figure-eight-fraction` of a turn one way, then back the other way
+    #[options(no_short)]
+    figure_eight_cmd: Option<String>,
+    /// Part of a full turn each loop of a figure-eight has to cover
+    #[options(no_short, default = "0.5")]
+    figure_eight_fraction: f32,
+    /// Time limit for both loops of a figure-eight
+    #[options(no_short, default = "1500")]
+    figure_eight_timeout_ms: u32,
+    /// Command to run when starting listening touchscreen
+    #[options(no_short)]
+    on_listen_cmd: Option<String>,
+    /// Command to run when going back to listening keyboard, after timeout or cancel
+    #[options(no_short)]
+    on_idle_cmd: Option<String>,
+    /// Command to run while listening touchscreen, getting `X Y TIMESTAMP` lines of touch points on stdin
+    #[options(no_short)]
+    stream_cmd: Option<String>,
+    /// Stop listening touchscreen on a tap outside all rings (or inside `--cancel-region`)
+    #[options(no_short)]
+    tap_cancel: bool,
+    /// Region for cancelling taps, as `X,Y,R`
+    #[options(no_short, meta = "X,Y,R")]
+    cancel_region: Option<Circle>,
+    /// Longest touch that counts as a tap
+    #[options(no_short, default = "250")]
+    tap_max_ms: u32,
+    /// Largest movement during a touch that still counts as a tap
+    #[options(no_short, default = "40")]
+    tap_max_movement: u32,
+    /// Command to run when listening is cancelled by a tap
+    #[options(no_short)]
+    cancel_cmd: Option<String>,
+    /// Listen touchscreen only while the key is held, plus `--hold-grace-ms` after release
+    #[options(no_short)]
+    hold_to_listen: bool,
+    /// Time to keep listening after the key is released with `--hold-to-listen`
+    #[options(no_short, default = "300")]
+    hold_grace_ms: u32,
     #[options(short = 'F', default = "3")]
     cw_spins_required: usize,
     #[options(short = 'R', default = "2")]
@@ -41,227 +248,2017 @@ struct Opts {
     after_spin_attention_time_ms: u32,
     #[options(short = 'Q', default = "60000")]
     after_successful_cw_spin_sequence_attention_time: u32,
+    /// Time to keep listening after a completed counterclockwise sequence, like `-Q` (default: same as `-a`)
+    #[options(no_short)]
+    after_successful_ccw_spin_sequence_attention_time: Option<u32>,
+    /// Longest sleep while listening touchscreen without touch events (a power vs latency tradeoff)
+    #[options(no_short, default = "20")]
+    touch_poll_ms: u32,
+    /// Keep listening at least this long after any touch, 0 to disable
+    #[options(no_short, default = "0")]
+    extend_on_touch_ms: u32,
     #[options(short = 'G', default = "300")]
     gesture_timeout_ms: u32,
+    /// Lengthen `-G` for slow spins and shorten it for fast ones, `-G` being the timeout at one turn per second
+    #[options(no_short)]
+    adaptive_timeout: bool,
+    /// Lower bound of `--adaptive-timeout`
+    #[options(no_short, default = "100")]
+    min_gesture_timeout_ms: u32,
+    /// Upper bound of `--adaptive-timeout`
+    #[options(no_short, default = "2000")]
+    max_gesture_timeout_ms: u32,
+    /// Comma-separated list of keycodes, any of which starts listening touchscreen
     #[options(short = 'K', default = "116")]
-    keycode_to_monitor: u16,
+    keycode_to_monitor: KeyCodes,
+    /// Ignore presses of the same arming key within this time after an accepted one
+    #[options(no_short, default = "200")]
+    key_debounce_ms: u32,
+    /// Comma-separated keycodes that all must be held down for the arming key to work
+    #[options(no_short, meta = "LIST")]
+    modifier_keycodes: Option<KeyCodes>,
+    /// Key code on the touch device itself (e.g. 272 for BTN_LEFT) that starts listening, no keyboard needed then
+    #[options(no_short, meta = "KEYCODE")]
+    trigger_button: Option<u16>,
+    /// Named pipe (created if missing) where writing anything starts listening touchscreen, no keyboard needed then
+    #[options(no_short, meta = "PATH")]
+    arm_fifo: Option<PathBuf>,
+    /// Keycode that stops listening touchscreen right away
+    #[options(no_short, meta = "KEYCODE")]
+    disarm_keycode: Option<u16>,
     /// Reset gesture attempt if this changes by more that this
     #[options(short = 'J', default = "200")]
     max_jump_distance: u32,
+    /// Reset gesture attempt if the touch moves faster than this (units per second) between samples, instead of `-J`
+    #[options(no_short, meta = "SPEED")]
+    max_jump_velocity: Option<u32>,
+    /// Reset gesture attempt only after this many consecutive jumps, dropping fewer as outliers
+    #[options(no_short, default = "2")]
+    jump_samples: u32,
+    /// Set log level to debug. Use RUST_LOG for finer control.
     #[options(short = 'D')]
     debug: bool,
-    #[options(short='c', default = "/data/data/com.termux/files/home/bin/torchctl up")]
+    /// More verbose log: -v for debug, -vv for trace
+    #[options(short = 'v', count)]
+    verbose: u32,
+    /// Comma-separated log categories to show below warning level: state, key, touch, spin, cancel, cmd
+    #[options(no_short, meta = "LIST")]
+    log_filter: Option<String>,
+    /// Keep lifetime clockwise and counterclockwise spin counts in this TOML file
+    #[options(no_short, meta = "PATH")]
+    state_file: Option<PathBuf>,
+    /// Also write usage statistics printed on exit to this file
+    #[options(no_short, meta = "PATH")]
+    stats_file: Option<PathBuf>,
+    /// Accept commands like `set radius 400` or `status` on this Unix socket
+    #[options(no_short, meta = "PATH")]
+    control_socket: Option<PathBuf>,
+    /// Emit `SpinCompleted` signals on the D-Bus session bus (needs the `dbus` cargo feature)
+    #[options(no_short)]
+    dbus: bool,
+    /// Publish completed spin sequences to this MQTT broker, `HOST[:PORT]` (needs the `mqtt` cargo feature)
+    #[options(no_short, meta = "HOST[:PORT]")]
+    mqtt_broker: Option<String>,
+    /// Topic for `--mqtt-broker` messages
+    #[options(no_short, default = "andrgesture/spin")]
+    mqtt_topic: String,
+    /// Vibrate on each spin and, longer, on completed sequences
+    #[options(no_short)]
+    haptic: bool,
+    /// Timed output to write vibration durations to for `--haptic`, if it exists
+    #[options(
+        no_short,
+        default = "/sys/class/timed_output/vibrator/enable",
+        meta = "PATH"
+    )]
+    haptic_sysfs: PathBuf,
+    /// Input device to play FF_RUMBLE effects on for `--haptic` instead of `--haptic-sysfs` (default: first one supporting it)
+    #[options(no_short, meta = "PATH")]
+    haptic_device: Option<PathBuf>,
+    /// Vibration length on a spin
+    #[options(no_short, default = "30")]
+    haptic_ms: u32,
+    /// Vibration length on a completed sequence
+    #[options(no_short, default = "100")]
+    haptic_sequence_ms: u32,
+    /// Read device events from a file saved by the `record` subcommand instead of devices
+    #[options(no_short, meta = "FILE")]
+    replay: Option<PathBuf>,
+    /// Replay events as fast as possible, using recorded timing as a virtual clock
+    #[options(no_short)]
+    replay_fast: bool,
+    /// Print events as JSON lines to stdout, moving the log to stderr
+    #[options(no_short)]
+    json: bool,
+    /// Don't print the effective configuration at startup
+    #[options(short = 'q')]
+    quiet: bool,
+    /// Fail instead of warning when the ring does not fit into the touch device coordinate range
+    #[options(no_short)]
+    strict: bool,
+    /// Grab touch devices while listening, so that touches don't reach other programs
+    #[options(no_short)]
+    grab: bool,
+    /// Retry opening devices at startup this many times before giving up
+    #[options(no_short, default = "0")]
+    open_retries: u32,
+    /// Consider a device broken after `poll` reported it ready this many times in a row without events, 0 to disable
+    #[options(no_short, default = "100")]
+    max_empty_reads: u32,
+    /// What to do with a broken device: `reopen` it or `exit` with an error
+    #[options(no_short, default = "reopen", meta = "reopen|exit")]
+    empty_reads_action: devices::EmptyReadsAction,
+    /// Delay between `--open-retries`
+    #[options(no_short, default = "1000")]
+    open_retry_delay_ms: u32,
+    /// Don't repeat a spin sequence command (`-c`, `-C` or zone commands) within this time, per direction
+    #[options(no_short, default = "0")]
+    cooldown_ms: u32,
+    /// Shell to run commands with
+    #[options(no_short, default = "sh")]
+    shell: String,
+    /// Execute commands directly, splitting them into arguments by whitespace and quotes, without a shell
+    #[options(no_short)]
+    no_shell: bool,
+    /// Directory where relative programs of commands are looked up first
+    #[options(no_short, meta = "DIR")]
+    cmd_dir: Option<PathBuf>,
+    /// Print commands instead of running them
+    #[options(no_short)]
+    dry_run: bool,
+    /// Terminate commands still running after this time (SIGTERM, then SIGKILL a second later), 0 to disable
+    #[options(no_short, default = "0")]
+    cmd_timeout_ms: u32,
+    /// Most commands running at once, 0 for no limit
+    #[options(no_short, default = "0")]
+    max_concurrent_cmds: usize,
+    /// What to do with a command while `--max-concurrent-cmds` are running: `drop` it or `queue` it until another exits
+    #[options(no_short, default = "drop", meta = "drop|queue")]
+    cmd_overflow: commands::Overflow,
+    /// Command to run after enough clockwise spins, or `key:KEY_NAME[:HOLD_MS]` to emit a key via uinput. Substitutes `{spins}`, `{direction}`, `{total_angle}` (degrees); `{{`/`}}` are literal braces.
+    #[options(
+        short = 'c',
+        default = "/data/data/com.termux/files/home/bin/torchctl up"
+    )]
     cmdline_for_cw_spins: String,
-    #[options(short='C', default = "/data/data/com.termux/files/home/bin/torchctl down")]
+    /// Command to run after enough counterclockwise spins. Same substitutions as for `-c`.
+    #[options(
+        short = 'C',
+        default = "/data/data/com.termux/files/home/bin/torchctl down"
+    )]
     cmdline_for_ccw_spins: String,
+    /// Command to run on each clockwise spin, in addition to `-c` when enough spins are done
+    #[options(no_short)]
+    per_spin_cw_cmd: Option<String>,
+    /// Command to run on each counterclockwise spin, in addition to `-C` when enough spins are done
+    #[options(no_short)]
+    per_spin_ccw_cmd: Option<String>,
+    /// Command to run with `{level}` when the absolute level changes: the level at gesture start plus `--level-step` per counted spin
+    #[options(no_short)]
+    level_cmd: Option<String>,
+    /// Level to start from, unless kept in `--state-file`
+    #[options(no_short, default = "0")]
+    level_base: i32,
+    /// Change of level per spin, counterclockwise spins decrease it
+    #[options(no_short, default = "1")]
+    level_step: i32,
+    /// Highest level, the lowest one is 0
+    #[options(no_short, default = "100")]
+    level_max: i32,
+    /// Command to run once the clockwise spin count reaches `-F`, instead of `-c` and `--per-spin-cw-cmd` for that spin
+    #[options(no_short)]
+    cw_sequence_cmd: Option<String>,
+    /// Command to run once the counterclockwise spin count reaches `-R`, instead of `-C` and `--per-spin-ccw-cmd` for that spin
+    #[options(no_short)]
+    ccw_sequence_cmd: Option<String>,
+    /// Command to run with `{value}` (0-100) going up on clockwise and down on counterclockwise spinning, kept between gestures
+    #[options(no_short)]
+    analog_cmd: Option<String>,
+    /// How much `{value}` of `--analog-cmd` changes per turn
+    #[options(no_short, default = "25")]
+    analog_sensitivity: f32,
+    /// `{value}` of `--analog-cmd` before the first spin
+    #[options(no_short, default = "0")]
+    analog_initial: f32,
+    /// Minimal interval between runs of `--analog-cmd`
+    #[options(no_short, default = "200")]
+    analog_debounce_ms: u32,
+    /// Emit REL_WHEEL events through a virtual uinput device while spinning, clockwise being positive
+    #[options(no_short)]
+    scroll_wheel: bool,
+    /// Spin angle per `--scroll-wheel` detent, in degrees
+    #[options(no_short, default = "15")]
+    scroll_angle: f32,
+    /// Command to run with `{fraction}` (0-1) of the way to the next spin, while spinning
+    #[options(no_short)]
+    progress_cmd: Option<String>,
+    /// Minimal interval between runs of `--progress-cmd`
+    #[options(no_short, default = "100")]
+    progress_interval_ms: u32,
+}
+
+#[derive(Options)]
+enum Command {
+    /// Check spin detection on synthetic circles and measure its throughput
+    Bench(bench::BenchOpts),
+    /// Find out center and radius for the gesture
+    Calibrate(calibrate::CalibrateOpts),
+    /// List input devices, marking keyboard and touchscreen candidates
+    ListDevices(devices::ListDevicesOpts),
+    /// Dump touch device events along with derived point and angle
+    Monitor(monitor::MonitorOpts),
+    /// Save keyboard and touch device events to a file for `--replay`
+    Record(replay::RecordOpts),
 }
 
 type Error = Box<dyn std::error::Error + Send + Sync>;
 
-type Point = euclid::Point2D<f32, UnknownUnit>;
-type Angle = euclid::Angle<f32>;
+/// Log targets for `--log-filter` categories, also usable in `RUST_LOG`
+const LOG_STATE: &str = "andrgesture::state";
+const LOG_KEY: &str = "andrgesture::key";
+const LOG_TOUCH: &str = "andrgesture::touch";
+const LOG_SPIN: &str = "andrgesture::spin";
+const LOG_CMD: &str = "andrgesture::commands";
+const LOG_CANCEL: &str = "andrgesture::cancel";
+
+fn log_target(category: &str) -> Result<&'static str, Error> {
+    Ok(match category {
+        "state" => LOG_STATE,
+        "key" => LOG_KEY,
+        "touch" => LOG_TOUCH,
+        "spin" => LOG_SPIN,
+        "cmd" => LOG_CMD,
+        "cancel" => LOG_CANCEL,
+        _ => return Err(format!("Unknown log category `{}`", category).into()),
+    })
+}
+
+/// Verbose log format: milliseconds since start, colored by category if `color`
+fn log_format(
+    start: Instant,
+    color: bool,
+) -> impl Fn(&mut env_logger::fmt::Formatter, &log::Record) -> std::io::Result<()> {
+    move |buf, record| {
+        use std::io::Write;
+        let t = start.elapsed();
+        let code = match (record.level(), record.target()) {
+            (log::Level::Error | log::Level::Warn, _) | (_, LOG_CANCEL) => "31",
+            (_, LOG_TOUCH) => "90",
+            (_, LOG_SPIN) => "32",
+            (_, LOG_STATE) => "36",
+            _ => "0",
+        };
+        let line = format!(
+            "[{}.{:03} {:<5} {}] {}",
+            t.as_secs(),
+            t.subsec_millis(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        match color {
+            true => writeln!(buf, "\x1b[{}m{}\x1b[0m", code, line),
+            false => writeln!(buf, "{}", line),
+        }
+    }
+}
+
+struct KeyCodes(Vec<u16>);
+
+impl std::str::FromStr for KeyCodes {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(KeyCodes(
+            s.split(',')
+                .map(|x| x.trim().parse())
+                .collect::<Result<_, _>>()?,
+        ))
+    }
+}
+
+#[derive(Clone, Copy, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum GestureType {
+    Spin,
+    Swipe,
+    Both,
+}
+
+impl GestureType {
+    fn spin(self) -> bool {
+        matches!(self, GestureType::Spin | GestureType::Both)
+    }
+
+    fn swipe(self) -> bool {
+        matches!(self, GestureType::Swipe | GestureType::Both)
+    }
+}
+
+impl std::str::FromStr for GestureType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "spin" => Ok(GestureType::Spin),
+            "swipe" => Ok(GestureType::Swipe),
+            "both" => Ok(GestureType::Both),
+            _ => Err(format!(
+                "Unknown gesture type `{}`, expected spin, swipe or both",
+                s
+            )),
+        }
+    }
+}
+
+/// Comma-separated pair of numbers, e.g. `0.5,0.5`
+struct FracPair(f32, f32);
+
+impl std::str::FromStr for FracPair {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s
+            .split_once(',')
+            .ok_or("Expected two comma-separated numbers")?;
+        Ok(FracPair(x.trim().parse()?, y.trim().parse()?))
+    }
+}
+
+/// Comma-separated center and radius, e.g. `100,100,50`
+struct Circle {
+    center: Point,
+    radius: f32,
+}
+
+impl std::str::FromStr for Circle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let v: Vec<f32> = s
+            .split(',')
+            .map(|x| x.trim().parse())
+            .collect::<Result<_, _>>()?;
+        match v[..] {
+            [x, y, radius] => Ok(Circle {
+                center: point2(x, y),
+                radius,
+            }),
+            _ => Err("Expected three comma-separated numbers".into()),
+        }
+    }
+}
+
+/// Value of `--device-ring`
+struct DeviceRing {
+    path: PathBuf,
+    ring: Circle,
+}
+
+impl std::str::FromStr for DeviceRing {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, ring) = s.rsplit_once('=').ok_or("Expected PATH=X,Y,R")?;
+        let ring: Circle = ring.parse()?;
+        if ring.radius <= 0.0 {
+            return Err("Device ring radius must be positive".into());
+        }
+        Ok(DeviceRing {
+            path: path.into(),
+            ring,
+        })
+    }
+}
+
+/// Maps coordinates of a touch device with its own `--device-ring` onto the global ring
+struct RingMapping {
+    from: Point,
+    to: Point,
+    scale: f32,
+}
+
+impl RingMapping {
+    fn apply(&self, p: Point) -> Point {
+        self.to + (p - self.from) * self.scale
+    }
+}
 
-#[derive(derive_new::new)]
-struct GestureState {
+/// Mapping for each touch device, `None` for devices using the global ring as is
+fn ring_mappings(opts: &Opts) -> Vec<Option<RingMapping>> {
+    for d in &opts.device_ring {
+        if !opts.touchpad_file.contains(&d.path) {
+            log::warn!(
+                "--device-ring {} is not a touch device given with -t",
+                d.path.display()
+            );
+        }
+    }
+    let to = point2(opts.center_x, opts.center_y).to_f32();
+    opts.touchpad_file
+        .iter()
+        .map(|path| {
+            let d = opts.device_ring.iter().find(|d| d.path == *path)?;
+            Some(RingMapping {
+                from: d.ring.center,
+                to,
+                scale: opts.radius as f32 / d.ring.radius,
+            })
+        })
+        .collect()
+}
+
+fn device_transform(opts: &Opts, dev: &Input) -> Result<Transform, Error> {
+    let mut t = Transform {
+        rotation: opts.rotation,
+        flip_x: opts.flip_x,
+        flip_y: opts.flip_y,
+        x_range: (0.0, 0.0),
+        y_range: (0.0, 0.0),
+    };
+    if !t.is_identity() {
+        let dev = dev.device()?;
+        let (ax, ay) = devices::position_axes(dev);
+        let x = devices::axis_range(dev, ax)?;
+        let y = devices::axis_range(dev, ay)?;
+        t.x_range = (x.0 as f32, x.1 as f32);
+        t.y_range = (y.0 as f32, y.1 as f32);
+    }
+    Ok(t)
+}
+
+/// Warn (or fail with `--strict`) if a ring does not fit into the touch device coordinate range
+fn check_zones(
+    opts: &Opts,
+    touches: &[Input],
+    mappings: &[Option<RingMapping>],
+    zones: &[zones::Zone],
+) -> Result<(), Error> {
+    if opts.relative {
+        return Ok(());
+    }
+    for ((touch, path), mapping) in touches.iter().zip(&opts.touchpad_file).zip(mappings) {
+        let Ok(dev) = touch.device() else {
+            continue;
+        };
+        let (ax, ay) = devices::position_axes(dev);
+        let x = devices::axis_range(dev, ax);
+        let y = devices::axis_range(dev, ay);
+        let (Ok(mut x), Ok(mut y)) = (x, y) else {
+            continue;
+        };
+        // Center is specified in rotated coordinates
+        if opts.rotation == 90 || opts.rotation == 270 {
+            std::mem::swap(&mut x, &mut y);
+        }
+        if let Some(m) = mapping {
+            let lo = m.apply(point2(x.0, y.0).to_f32()).to_i32();
+            let hi = m.apply(point2(x.1, y.1).to_f32()).to_i32();
+            (x, y) = ((lo.x, hi.x), (lo.y, hi.y));
+        }
+        for z in zones {
+            let r = z.config.radius as i32;
+            let fits = z.center_x - r >= x.0
+                && z.center_x + r <= x.1
+                && z.center_y - r >= y.0
+                && z.center_y + r <= y.1;
+            if fits {
+                continue;
+            }
+            let msg = format!(
+                "Zone {} (center {} {}, radius {}) does not fit into range x {}..{}, y {}..{} of {}",
+                z.name,
+                z.center_x,
+                z.center_y,
+                r,
+                x.0,
+                x.1,
+                y.0,
+                y.1,
+                path.display()
+            );
+            if opts.strict {
+                return Err(ExitCode::Usage.wrap(msg));
+            }
+            log::warn!("{}", msg);
+        }
+    }
+    Ok(())
+}
+
+/// Convert `--center-frac` and `--radius-frac` to absolute device units
+fn apply_fractions(opts: &mut Opts, dev: &Input) -> Result<(), Error> {
+    if opts.center_frac.is_none() && opts.radius_frac.is_none() {
+        return Ok(());
+    }
+    let dev = dev.device()?;
+    let (ax, ay) = devices::position_axes(dev);
+    let x = devices::axis_range(dev, ax)?;
+    let y = devices::axis_range(dev, ay)?;
+    let lerp = |(min, max): (i32, i32), f: f32| min + ((max - min) as f32 * f) as i32;
+    if let Some(FracPair(fx, fy)) = opts.center_frac {
+        opts.center_x = lerp(x, fx);
+        opts.center_y = lerp(y, fy);
+    }
+    if let Some(f) = opts.radius_frac {
+        let span = (x.1 - x.0).min(y.1 - y.0);
+        opts.radius = (span as f32 * f) as i32;
+    }
+    log::info!(
+        "Using center {} {} and radius {}",
+        opts.center_x,
+        opts.center_y,
+        opts.radius
+    );
+    Ok(())
+}
+
+/// Usage of the requested (sub)command, as printed by `gumdrop::parse_args_or_exit`
+fn print_usage(program: &str, opts: &Opts) {
+    let mut command: &dyn Options = opts;
+    let mut name = String::new();
+    while let Some(c) = command.command() {
+        command = c;
+        if let Some(n) = c.command_name() {
+            name = name + " " + n;
+        }
+    }
+    eprintln!("Usage: {}{} [OPTIONS]\n", program, name);
+    eprintln!("{}", command.self_usage());
+    if let Some(cmds) = command.self_command_list() {
+        eprintln!("\nAvailable commands:\n{}", cmds);
+    }
+}
+
+/// Like `gumdrop::parse_args_or_exit`, but also takes `--config` file and `--no-FLAG` options into account.
+fn parse_opts() -> Opts {
+    let style = gumdrop::ParsingStyle::AllOptions;
+    let mut cli_args: Vec<String> = std::env::args().collect();
+    let program = cli_args.remove(0);
+    let cli_opts = Opts::parse_args(&config::merge_args(vec![], cli_args.clone()), style)
+        .unwrap_or_else(|e| {
+            eprintln!("{}: {}", program, e);
+            ExitCode::Usage.exit();
+        });
+    if cli_opts.help_requested() {
+        print_usage(&program, &cli_opts);
+        ExitCode::Clean.exit();
+    }
+    let mut opts = match cli_opts.config {
+        Some(ref config) => {
+            let file_args = config::load_as_args(config).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                ExitCode::Usage.exit();
+            });
+            let args = config::merge_args(file_args, cli_args);
+            let mut opts = Opts::parse_args(&args, style).unwrap_or_else(|e| {
+                eprintln!("{}: {}", config.display(), e);
+                ExitCode::Usage.exit();
+            });
+            // Repeatable options accumulate instead of overriding
+            if !cli_opts.keybd_file.is_empty() {
+                opts.keybd_file = cli_opts.keybd_file;
+            }
+            if !cli_opts.touchpad_file.is_empty() {
+                opts.touchpad_file = cli_opts.touchpad_file;
+            }
+            if !cli_opts.zone.is_empty() {
+                opts.zone = cli_opts.zone;
+            }
+            opts
+        }
+        None => cli_opts,
+    };
+    if opts.keybd_file.is_empty() && opts.trigger_button.is_none() && opts.arm_fifo.is_none() {
+        opts.keybd_file.push("/dev/input/event0".into());
+    }
+    if opts.touchpad_file.is_empty() {
+        opts.touchpad_file.push("/dev/input/event2".into());
+    }
+    opts
+}
+
+/// Set from SIGINT/SIGTERM handler
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+/// Set by SIGUSR1, to pause or resume arming
+static TOGGLE_PAUSE: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_terminate_signal(_: nix::libc::c_int) {
+    TERMINATE.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_pause_signal(_: nix::libc::c_int) {
+    TOGGLE_PAUSE.store(true, Ordering::SeqCst);
+}
+
+/// Only needed to interrupt `poll`, so that children get reaped promptly
+extern "C" fn on_child_signal(_: nix::libc::c_int) {}
+
+fn install_signal_handlers() -> Result<(), Error> {
+    // No SA_RESTART, so that `poll` gets interrupted
+    let sa = SigAction::new(
+        SigHandler::Handler(on_terminate_signal),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    for sig in [Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { nix::sys::signal::sigaction(sig, &sa)? };
+    }
+    let sa = SigAction::new(
+        SigHandler::Handler(on_child_signal),
+        SaFlags::SA_NOCLDSTOP,
+        SigSet::empty(),
+    );
+    unsafe { nix::sys::signal::sigaction(Signal::SIGCHLD, &sa)? };
+    let sa = SigAction::new(
+        SigHandler::Handler(on_pause_signal),
+        SaFlags::empty(),
+        SigSet::empty(),
+    );
+    unsafe { nix::sys::signal::sigaction(Signal::SIGUSR1, &sa)? };
+    Ok(())
+}
+
+/// `nix::poll::poll`, but returning 0 ready descriptors when interrupted by a signal
+fn poll(fds: &mut [PollFd], timeout: nix::libc::c_int) -> Result<nix::libc::c_int, Error> {
+    match nix::poll::poll(fds, timeout) {
+        Err(Errno::EINTR) => Ok(0),
+        x => Ok(x?),
+    }
+}
+
+/// Shorten `poll` timeout in milliseconds (negative for none) to wake up at `at`
+fn timeout_until(timeout: nix::libc::c_int, now: Instant, at: Instant) -> nix::libc::c_int {
+    let due = at.saturating_duration_since(now).as_millis() as nix::libc::c_int + 1;
+    match timeout {
+        t if t < 0 => due,
+        t => t.min(due),
+    }
+}
+
+/// `poll` for live devices, or waiting for the next due event when replaying
+fn wait_input(
+    fds: &mut [PollFd],
+    timeout: nix::libc::c_int,
+    replay: &mut Option<replay::Replay>,
+) -> Result<nix::libc::c_int, Error> {
+    match replay {
+        Some(r) => {
+            r.wait(timeout)?;
+            // Other descriptors, e.g. the control socket, are still served
+            poll(fds, 0)?;
+            Ok(1)
+        }
+        None => poll(fds, timeout),
+    }
+}
+
+/// Current time, virtual when replaying with `--replay-fast`
+fn clock(replay: &Option<replay::Replay>) -> Instant {
+    replay
+        .as_ref()
+        .map_or_else(Instant::now, replay::Replay::now)
+}
+
+/// Option checks that don't need devices, done before opening any
+fn check_opts(opts: &Opts) -> Result<(), Error> {
+    if ![0, 90, 180, 270].contains(&opts.rotation) {
+        return Err("Rotation must be 0, 90, 180 or 270".into());
+    }
+    if opts.figure_eight_fraction <= 0.0 {
+        return Err("Figure-eight fraction must be positive".into());
+    }
+    if opts.scroll_angle <= 0.0 {
+        return Err("Scroll angle must be positive".into());
+    }
+    if opts.pinch_ratio <= 1.0 {
+        return Err("Pinch ratio must be greater than 1".into());
+    }
+    if !(0..=opts.level_max).contains(&opts.level_base) {
+        return Err("Level base must be between 0 and level max".into());
+    }
+    gesture_config(opts)?;
+    let mut cmds = gesture_cmds(opts);
+    let value_cmds = [
+        &opts.stream_cmd,
+        &opts.level_cmd,
+        &opts.analog_cmd,
+        &opts.progress_cmd,
+    ];
+    cmds.extend(value_cmds.into_iter().flatten());
+    new_runner(opts).check(&cmds)
+}
+
+fn new_runner(opts: &Opts) -> commands::Runner {
+    let shell = (!opts.no_shell).then(|| opts.shell.clone());
+    let mut runner = commands::Runner::new(opts.dry_run, shell);
+    if let Some(ref dir) = opts.cmd_dir {
+        runner.set_dir(dir.clone());
+    }
+    runner
+}
+
+/// Commands run on gestures, the ones that can be `key:` actions
+fn gesture_cmds(opts: &Opts) -> Vec<&String> {
+    let mut cmds: Vec<&String> = vec![];
+    if opts.zone.is_empty() {
+        cmds.extend([&opts.cmdline_for_cw_spins, &opts.cmdline_for_ccw_spins]);
+    }
+    for z in &opts.zone {
+        cmds.extend(z.cw_cmd.iter().chain(z.ccw_cmd.iter()));
+    }
+    let optional_cmds = [
+        &opts.per_spin_cw_cmd,
+        &opts.per_spin_ccw_cmd,
+        &opts.cw_sequence_cmd,
+        &opts.ccw_sequence_cmd,
+        &opts.swipe_up_cmd,
+        &opts.swipe_down_cmd,
+        &opts.swipe_left_cmd,
+        &opts.swipe_right_cmd,
+        &opts.pinch_in_cmd,
+        &opts.pinch_out_cmd,
+        &opts.figure_eight_cmd,
+        &opts.cancel_cmd,
+        &opts.reverse_cmd,
+        &opts.on_listen_cmd,
+        &opts.on_idle_cmd,
+    ];
+    cmds.extend(optional_cmds.into_iter().flatten());
+    cmds
+}
+
+/// Ring and spin detection parameters from the options
+fn gesture_config(opts: &Opts) -> Result<GestureConfig, Error> {
+    let inner_radius = match opts.inner_radius {
+        Some(r) => r as f32,
+        None => opts.radius as f32 / 8.0,
+    };
+    if !(0.0..1.0).contains(&opts.smoothing) {
+        return Err("Smoothing must be in [0, 1) range".into());
+    }
+    let cw_spin_fraction = opts.cw_spin_fraction.unwrap_or(opts.spin_fraction);
+    let ccw_spin_fraction = opts.ccw_spin_fraction.unwrap_or(opts.spin_fraction);
+    if cw_spin_fraction <= 0.0 || ccw_spin_fraction <= 0.0 {
+        return Err("Spin fraction must be positive".into());
+    }
+    Ok(GestureConfig {
+        center: point2(opts.center_x, opts.center_y).to_f32(),
+        radius: opts.radius as f32,
+        inner_radius,
+        radius_hysteresis: opts.radius_hysteresis as f32,
+        max_jump_distance: opts.max_jump_distance as f32,
+        max_jump_velocity: opts.max_jump_velocity.map(|v| v as f32),
+        jump_samples: opts.jump_samples,
+        gesture_timeout: Duration::from_millis(opts.gesture_timeout_ms as u64),
+        adaptive_timeout: opts.adaptive_timeout.then(|| {
+            let min = Duration::from_millis(opts.min_gesture_timeout_ms as u64);
+            let max = Duration::from_millis(opts.max_gesture_timeout_ms as u64);
+            (min, max)
+        }),
+        cw_spin_fraction,
+        ccw_spin_fraction,
+        smoothing: opts.smoothing,
+        min_angular_velocity: opts.min_angular_velocity,
+        min_samples_per_spin: opts.min_samples_per_spin,
+        keep_on_reversal: opts.keep_on_reversal,
+        min_quadrants: opts.min_quadrants,
+        continuous: opts
+            .continuous
+            .then(|| Duration::from_millis(opts.continuous_window_ms as u64)),
+        warmup: Duration::from_millis(opts.warmup_ms as u64),
+        max_duration: opts
+            .max_gesture_ms
+            .map(|ms| Duration::from_millis(ms as u64)),
+        angle_origin: Angle::degrees(opts.angle_origin),
+        invert_direction: opts.invert_direction,
+    })
+}
+
+fn new_tracker(opts: &Opts) -> touch::Tracker {
+    if opts.relative {
+        let x = opts.rel_start_x.unwrap_or(opts.center_x);
+        let y = opts.rel_start_y.unwrap_or(opts.center_y);
+        touch::Tracker::relative(touch::RelTracker::new(point2(x, y).to_f32()))
+    } else {
+        touch::Tracker::absolute(touch::MtTracker::new(
+            opts.min_pressure as i32,
+            opts.max_touch_major as i32,
+            opts.track,
+        ))
+    }
+}
+
+/// Kernel releases the grab when the process exits, even on panic
+fn set_grab(touches: &mut [Input], grab: bool) {
+    for t in touches {
+        if let Err(e) = t.set_grab(grab) {
+            log::warn!("Failed to change touch device grab: {}", e);
+        }
+    }
+}
+
+/// Gestures in progress in one zone of one touch device
+#[derive(Default)]
+struct Gestures {
+    spin: Option<GestureState>,
+    swipe: Option<SwipeState>,
+    figure_eight: Option<FigureEightState>,
+    /// `--level-cmd` level when the spin gesture started
+    level_start: i32,
+    /// Scroll wheel detents emitted for the spin gesture
+    wheel_ticks: i32,
+    /// Spin angle of the gesture already applied to `--analog-cmd`, in turns
+    analog_turns: f32,
+    /// The spin gesture hit `--max-gesture-ms`, don't start another one until the finger is lifted
+    expired: bool,
+}
+
+impl Gestures {
+    fn is_active(&self) -> bool {
+        self.spin.is_some() || self.swipe.is_some()
+    }
+}
+
+/// Listening touchscreen after arming
+struct Listening {
     deadline: Instant,
-    prev: Point,
-    prev_angle: Angle,
-    #[new(default)]
-    spinner: f32,
-    #[new(default)]
-    reacted_spin: f32,
+    /// Arming key that is still held with `--hold-to-listen`, the deadline doesn't apply
+    held: Option<u16>,
+    /// Key that started listening, selecting active zones
+    key: u16,
+    /// Per touch device, per zone
+    gestures: Vec<Vec<Gestures>>,
+    /// Per touch device
+    pinches: Vec<Option<PinchState>>,
+    /// Per touch device
+    taps: Vec<Option<TapState>>,
 }
 
 enum State {
     WaitingForKeyboard,
-    WaitingForTouches {
+    WaitingForTouches(Listening),
+}
+
+impl State {
+    /// Snapshot for the `status` control command
+    fn status<'a>(
+        &self,
+        now: Instant,
+        paused: bool,
+        stats: &'a stats::Stats,
+    ) -> control::Status<'a> {
+        let State::WaitingForTouches(l) = self else {
+            return control::Status {
+                state: "waiting_keyboard",
+                spinner: None,
+                deadline_left_ms: None,
+                paused,
+                stats,
+            };
+        };
+        let active = l.gestures.iter().flatten().any(Gestures::is_active);
+        let spinner = l
+            .gestures
+            .iter()
+            .flatten()
+            .filter_map(|g| g.spin.as_ref().map(|s| s.spinner))
+            .max_by(|a, b| a.abs().total_cmp(&b.abs()));
+        control::Status {
+            state: if active { "in_gesture" } else { "listening" },
+            spinner,
+            deadline_left_ms: l
+                .held
+                .is_none()
+                .then(|| l.deadline.saturating_duration_since(now).as_millis() as u64),
+            paused,
+            stats,
+        }
+    }
+
+    /// Listening touchscreen with no gestures in progress
+    fn listening(
         deadline: Instant,
-        gesture: Option<GestureState>,
-    },
+        held: Option<u16>,
+        key: u16,
+        touches: usize,
+        zones: usize,
+    ) -> State {
+        State::WaitingForTouches(Listening {
+            deadline,
+            held,
+            key,
+            gestures: (0..touches)
+                .map(|_| (0..zones).map(|_| Default::default()).collect())
+                .collect(),
+            pinches: (0..touches).map(|_| None).collect(),
+            taps: (0..touches).map(|_| None).collect(),
+        })
+    }
 }
 
-fn main() -> Result<(), Error> {
-    let opts: Opts = gumdrop::parse_args_or_exit(gumdrop::ParsingStyle::AllOptions);
-    let mut keydb = Device::open(opts.keybd_file)?;
-    let mut touch = Device::open(opts.touchpad_file)?;
+/// How long to keep listening touchscreen after each kind of input, from the options
+struct Attention {
+    button: Duration,
+    spin: Duration,
+    cw_sequence: Duration,
+    ccw_sequence: Duration,
+    hold_grace: Duration,
+    /// Zero if `--extend-on-touch-ms` is disabled
+    touch: Duration,
+}
 
-    nix::fcntl::fcntl(keydb.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
-    nix::fcntl::fcntl(touch.as_raw_fd(), FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+impl Attention {
+    fn new(opts: &Opts) -> Attention {
+        let ms = |ms: u32| Duration::from_millis(ms as u64);
+        let spin = ms(opts.after_spin_attention_time_ms);
+        Attention {
+            button: ms(opts.after_buttonpress_attention_time_ms),
+            spin,
+            cw_sequence: ms(opts.after_successful_cw_spin_sequence_attention_time),
+            ccw_sequence: opts
+                .after_successful_ccw_spin_sequence_attention_time
+                .map_or(spin, ms),
+            hold_grace: ms(opts.hold_grace_ms),
+            touch: ms(opts.extend_on_touch_ms),
+        }
+    }
+}
 
-    let center: Point = point2(opts.center_x, opts.center_y).to_f32();
-    let sqradius = opts.radius as f32 * opts.radius as f32;
-    let sqmaxd = opts.max_jump_distance as f32 * opts.max_jump_distance as f32;
+/// How a touch frame affected listening
+enum Outcome {
+    Continue,
+    /// Tap cancelling listening
+    Cancelled,
+    /// Spin sequence completed in the zone with this index
+    Completed(usize),
+}
 
-    let mut state = State::WaitingForKeyboard;
+/// Reactions to gestures while listening: commands, events, counters and other outputs
+struct Dispatch<'a> {
+    opts: &'a Opts,
+    attention: Attention,
+    /// Per touch device
+    transforms: Vec<Transform>,
+    /// Per touch device
+    mappings: Vec<Option<RingMapping>>,
+    pinch_config: PinchConfig,
+    tap_config: TapConfig,
+    swipe_config: SwipeConfig,
+    figure_eight_config: FigureEightConfig,
+    runner: commands::Runner,
+    cooldown: commands::Cooldown,
+    stats: stats::Stats,
+    state_file: Option<state::StateFile>,
+    /// Current `--level-cmd` level
+    level: i32,
+    bus: Option<dbus::Bus>,
+    mqtt: Option<mqtt::Mqtt>,
+    haptic: Option<haptic::Haptic>,
+    wheel: Option<inject::ScrollWheel>,
+    analog: Option<commands::Analog>,
+    progress: Option<commands::Throttled>,
+}
 
-    loop {
-        let now = Instant::now();
+impl Dispatch<'_> {
+    /// Run throttled commands with pending values if they are due
+    fn poll_throttled(&mut self, now: Instant) -> Result<(), Error> {
+        if let Some(ref mut a) = self.analog {
+            a.poll(now, &mut self.runner)?;
+        }
+        if let Some(ref mut p) = self.progress {
+            p.poll(now, &mut self.runner)?;
+        }
+        Ok(())
+    }
 
-        match &mut state {
-            State::WaitingForKeyboard => {
-                let mut polls = [PollFd::new(keydb.as_raw_fd(), PollFlags::POLLIN)];
-                let stnow = SystemTime::now();
-                nix::poll::poll(&mut polls, -1)?;
+    /// Earliest time a throttled command with a pending value should run
+    fn throttled_due(&self, now: Instant) -> Option<Instant> {
+        let analog = self.analog.as_ref().and_then(|a| a.due(now));
+        let progress = self.progress.as_ref().and_then(|p| p.due(now));
+        analog.into_iter().chain(progress).min()
+    }
 
-                for ev in keydb.fetch_events()? {
-                    match ev.kind() {
-                        evdev::InputEventKind::Key(k) => {
-                            if opts.debug {
-                                println!("Key {}", k.0);
-                            }
-                            if ev.value() == 1 && k.0 == opts.keycode_to_monitor {
-                                let ts = ev.timestamp();
-                                match ts.duration_since(stnow) {
-                                    Ok(_) => {
-                                        println!("Listening touchscreen");
-                                        state = State::WaitingForTouches {
-                                            deadline: Instant::now()
-                                                + Duration::from_millis(
-                                                    opts.after_buttonpress_attention_time_ms as u64,
-                                                ),
-                                            gesture: None,
-                                        };
-                                    }
-                                    _ => {
-                                        println!("Stale key event");
-                                    }
-                                }
-                            }
+    /// React to a complete frame of the touch device `i`
+    fn frame(
+        &mut self,
+        zones: &[zones::Zone],
+        l: &mut Listening,
+        i: usize,
+        tracker: &mut touch::Tracker,
+        lifted: bool,
+        now: Instant,
+    ) -> Result<Outcome, Error> {
+        let opts = self.opts;
+        if lifted && opts.tap_cancel {
+            if let Some(p) = finish_tap(&mut l.taps[i], now, &self.tap_config) {
+                let in_region = match opts.cancel_region {
+                    Some(ref c) => (p - c.center).length() <= c.radius,
+                    None => zones
+                        .iter()
+                        .filter(|z| z.active_for(l.key))
+                        .all(|z| (p - z.config.center).length() > z.config.radius),
+                };
+                if in_region {
+                    log::info!(target: LOG_STATE, "Cancelled by tap");
+                    return Ok(Outcome::Cancelled);
+                }
+            }
+        }
+        let dev_gestures = &mut l.gestures[i];
+        if lifted && dev_gestures.iter().any(|g| g.is_active() || g.expired) {
+            log::debug!(target: LOG_TOUCH, "Finger lifted");
+            dev_gestures
+                .iter_mut()
+                .for_each(|g| *g = Default::default());
+        }
+        let pinch_enabled = opts.pinch_in_cmd.is_some() || opts.pinch_out_cmd.is_some();
+        if pinch_enabled && self.pinch(zones, l, i, tracker, now)? {
+            // Single-finger gestures don't apply while pinching
+            l.gestures[i]
+                .iter_mut()
+                .for_each(|g| *g = Default::default());
+            return Ok(Outcome::Continue);
+        }
+        let Some(p) = tracker.point() else {
+            log::trace!(target: LOG_TOUCH, "No touch");
+            return Ok(Outcome::Continue);
+        };
+        let mut p = self.transforms[i].apply(p);
+        if let Some(ref m) = self.mappings[i] {
+            p = m.apply(p);
+        }
+        self.runner.stream_point(p);
+        if !self.attention.touch.is_zero() {
+            l.deadline = l.deadline.max(now + self.attention.touch);
+        }
+        log::trace!(target: LOG_TOUCH, "Touch {} {}", p.x, p.y);
+        if opts.tap_cancel {
+            feed_tap(&mut l.taps[i], p, now, &self.tap_config);
+        }
+
+        for (j, (zone, g)) in zones.iter().zip(l.gestures[i].iter_mut()).enumerate() {
+            if !zone.active_for(l.key) {
+                continue;
+            }
+            let label = match opts.zone.is_empty() {
+                true => String::new(),
+                false => format!("[{}] ", zone.name),
+            };
+            if opts.gesture_type.swipe() {
+                self.swipe(zone, g, p, now, &mut l.deadline, &label)?;
+            }
+            if opts.gesture_type.spin() && self.spin(zone, g, p, now, &mut l.deadline, &label)? {
+                return Ok(Outcome::Completed(j));
+            }
+        }
+        Ok(Outcome::Continue)
+    }
+
+    /// Feed the two-finger pair to pinch detection, returning whether a pinch is in progress
+    fn pinch(
+        &mut self,
+        zones: &[zones::Zone],
+        l: &mut Listening,
+        i: usize,
+        tracker: &touch::Tracker,
+        now: Instant,
+    ) -> Result<bool, Error> {
+        let opts = self.opts;
+        let pinch = &mut l.pinches[i];
+        let event = feed_pinch(pinch, tracker.pair(), now, &self.pinch_config);
+        let (e, direction, cmd) = match event {
+            Some(e @ PinchEvent::In) => (e, "in", &opts.pinch_in_cmd),
+            Some(e @ PinchEvent::Out) => (e, "out", &opts.pinch_out_cmd),
+            Some(PinchEvent::Cancelled) => {
+                log::debug!(target: LOG_CANCEL, "Pinch cancelled");
+                return Ok(pinch.is_some());
+            }
+            None => return Ok(pinch.is_some()),
+        };
+        log::info!(target: LOG_SPIN, "PINCH {:?}", e);
+        events::emit(opts.json, events::Event::Pinch { direction });
+        let pinching = pinch.is_some();
+        l.deadline = now + self.attention.spin;
+        let Some(cmd) = cmd else {
+            return Ok(pinching);
+        };
+        // Zone whose ring is nearest to the midpoint of the fingers, the one containing it if any
+        let zone = tracker.pair().and_then(|(a, b)| {
+            let mut mid = self.transforms[i].apply(a.lerp(b, 0.5));
+            if let Some(ref m) = self.mappings[i] {
+                mid = m.apply(mid);
+            }
+            let outside = |z: &&zones::Zone| (mid - z.config.center).length() - z.config.radius;
+            zones
+                .iter()
+                .filter(|z| z.active_for(l.key))
+                .min_by(|a, b| outside(a).total_cmp(&outside(b)))
+        });
+        let Some(zone) = zone else {
+            log::debug!(target: LOG_SPIN, "No zone is active for the pinch");
+            return Ok(pinching);
+        };
+        let info = commands::SpinInfo {
+            direction,
+            spins: 0,
+            total_angle: 0.0,
+            center_x: zone.center_x,
+            center_y: zone.center_y,
+            zone: &zone.name,
+        };
+        self.runner.spawn(cmd, &info)?;
+        Ok(pinching)
+    }
+
+    fn swipe(
+        &mut self,
+        zone: &zones::Zone,
+        g: &mut Gestures,
+        p: Point,
+        now: Instant,
+        deadline: &mut Instant,
+        label: &str,
+    ) -> Result<(), Error> {
+        let opts = self.opts;
+        let Some(dir) = feed_swipe(&mut g.swipe, p, now, &zone.config, &self.swipe_config) else {
+            return Ok(());
+        };
+        log::info!(target: LOG_SPIN, "{}SWIPE {}", label, dir.as_str());
+        let ev = events::Event::Swipe {
+            direction: dir.as_str(),
+            zone: &zone.name,
+        };
+        events::emit(opts.json, ev);
+        *deadline = now + self.attention.spin;
+        let cmd = match dir {
+            SwipeDirection::Up => &opts.swipe_up_cmd,
+            SwipeDirection::Down => &opts.swipe_down_cmd,
+            SwipeDirection::Left => &opts.swipe_left_cmd,
+            SwipeDirection::Right => &opts.swipe_right_cmd,
+        };
+        if let Some(cmd) = cmd {
+            let info = commands::SpinInfo {
+                direction: dir.as_str(),
+                spins: 0,
+                total_angle: 0.0,
+                center_x: zone.center_x,
+                center_y: zone.center_y,
+                zone: &zone.name,
+            };
+            self.runner.spawn(cmd, &info)?;
+        }
+        Ok(())
+    }
+
+    /// Feed the point to spin detection in the zone, returning whether the spin sequence got completed
+    fn spin(
+        &mut self,
+        zone: &zones::Zone,
+        g: &mut Gestures,
+        p: Point,
+        now: Instant,
+        deadline: &mut Instant,
+        label: &str,
+    ) -> Result<bool, Error> {
+        if g.expired {
+            return Ok(false);
+        }
+        if g.spin.is_none() {
+            g.wheel_ticks = 0;
+            g.analog_turns = 0.0;
+            g.figure_eight = None;
+            g.level_start = self.level;
+        }
+        let event = feed_point(&mut g.spin, p, now, &zone.config);
+        if self.spinning(zone, g, now, label)? {
+            *deadline = now + self.attention.spin;
+        }
+        match event {
+            Some(event) => self.spin_event(zone, g, event, now, deadline, label),
+            None => Ok(false),
+        }
+    }
+
+    /// Outputs following the spin angle of a gesture in progress, returning whether a figure-eight got completed
+    fn spinning(
+        &mut self,
+        zone: &zones::Zone,
+        g: &mut Gestures,
+        now: Instant,
+        label: &str,
+    ) -> Result<bool, Error> {
+        let opts = self.opts;
+        let Some(spinner) = g.spin.as_ref().map(|s| s.spinner) else {
+            return Ok(false);
+        };
+        log::trace!(target: LOG_SPIN, "Spinner {:.1}", spinner);
+        if opts.scroll_wheel {
+            let ticks = (spinner * 360.0 / opts.scroll_angle).trunc() as i32;
+            let detents = ticks - g.wheel_ticks;
+            if detents != 0 {
+                g.wheel_ticks = ticks;
+                match self.wheel {
+                    Some(ref mut w) => w.emit(detents)?,
+                    None => log::info!(target: LOG_CMD, "would scroll: {}", detents),
+                }
+            }
+        }
+        if let Some(ref mut a) = self.analog {
+            a.turn(spinner - g.analog_turns);
+            g.analog_turns = spinner;
+        }
+        if let Some(ref mut p) = self.progress {
+            let fraction = match spinner >= 0.0 {
+                true => zone.config.cw_spin_fraction,
+                false => zone.config.ccw_spin_fraction,
+            };
+            let spins = spinner.abs() / fraction;
+            p.update(format!("{:.2}", spins.fract()));
+        }
+        let Some(ref cmd) = opts.figure_eight_cmd else {
+            return Ok(false);
+        };
+        let fig = &mut g.figure_eight;
+        let Some(first) = feed_figure_eight(fig, spinner, now, &self.figure_eight_config) else {
+            return Ok(false);
+        };
+        log::info!(target: LOG_SPIN, "{}FIGURE EIGHT {}", label, first.as_str());
+        let ev = events::Event::FigureEight {
+            first: first.as_str(),
+            zone: &zone.name,
+        };
+        events::emit(opts.json, ev);
+        let info = commands::SpinInfo {
+            direction: first.as_str(),
+            spins: 0,
+            total_angle: 0.0,
+            center_x: zone.center_x,
+            center_y: zone.center_y,
+            zone: &zone.name,
+        };
+        self.runner.spawn(cmd, &info)?;
+        Ok(true)
+    }
+
+    /// Run commands and update counters for a spin event, returning whether it completed the sequence
+    fn spin_event(
+        &mut self,
+        zone: &zones::Zone,
+        g: &mut Gestures,
+        event: SpinEvent,
+        now: Instant,
+        deadline: &mut Instant,
+        label: &str,
+    ) -> Result<bool, Error> {
+        let opts = self.opts;
+        let mut cmds: Vec<&str> = vec![];
+        let mut sequence_done = false;
+        match event {
+            SpinEvent::CwSpin(ctr) => {
+                *deadline = now + self.attention.spin;
+                // The spin reaching the count has its own command if configured
+                let reaching =
+                    ctr == zone.cw_spins_required as i32 && opts.cw_sequence_cmd.is_some();
+                if ctr >= zone.cw_spins_required as i32 {
+                    *deadline = now + self.attention.cw_sequence;
+                    log::info!(target: LOG_SPIN, "{}SPIN CW {} !", label, ctr);
+                    if self.cooldown.allow(&format!("{}:cw", zone.name), now) {
+                        match reaching {
+                            true => cmds.extend(opts.cw_sequence_cmd.as_deref()),
+                            false => cmds.extend(zone.cw_cmd.as_deref()),
+                        }
+                    } else {
+                        log::info!(
+                            target: LOG_CMD,
+                            "{}Not running the command during cooldown",
+                            label
+                        );
+                    }
+                    sequence_done = true;
+                } else {
+                    log::info!(target: LOG_SPIN, "{}SPIN CW {}", label, ctr);
+                }
+                if !reaching {
+                    cmds.extend(opts.per_spin_cw_cmd.as_deref());
+                }
+            }
+            SpinEvent::CcwSpin(ctr) => {
+                *deadline = now + self.attention.spin;
+                // The spin reaching the count has its own command if configured
+                let reaching =
+                    -ctr == zone.ccw_spins_required as i32 && opts.ccw_sequence_cmd.is_some();
+                if -ctr >= zone.ccw_spins_required as i32 {
+                    *deadline = now + self.attention.ccw_sequence;
+                    log::info!(target: LOG_SPIN, "{}SPIN CCW {} !", label, ctr);
+                    if self.cooldown.allow(&format!("{}:ccw", zone.name), now) {
+                        match reaching {
+                            true => cmds.extend(opts.ccw_sequence_cmd.as_deref()),
+                            false => cmds.extend(zone.ccw_cmd.as_deref()),
                         }
-                        _ => (),
+                    } else {
+                        log::info!(
+                            target: LOG_CMD,
+                            "{}Not running the command during cooldown",
+                            label
+                        );
                     }
+                    sequence_done = true;
+                } else {
+                    log::info!(target: LOG_SPIN, "{}SPIN CCW {}", label, ctr);
                 }
+                if !reaching {
+                    cmds.extend(opts.per_spin_ccw_cmd.as_deref());
+                }
+            }
+            SpinEvent::Cancelled(CancelReason::OppositeDirection) => {
+                log::info!(target: LOG_CANCEL, "{}Spinned in the opposite direction", label);
+                if let Some(ref cmd) = opts.reverse_cmd {
+                    self.runner.spawn_plain(cmd)?;
+                }
+            }
+            SpinEvent::Cancelled(CancelReason::MaxDuration) => {
+                log::info!(target: LOG_CANCEL, "{}Gesture lasted too long", label);
+                g.expired = true;
+            }
+            SpinEvent::Cancelled(reason) => {
+                log::debug!(target: LOG_CANCEL, "{}Gesture cancelled: {}", label, reason.as_str());
             }
-            State::WaitingForTouches {
-                deadline: touch_deadline,
-                gesture,
-            } => {
-                let mut polls = [PollFd::new(touch.as_raw_fd(), PollFlags::POLLIN)];
-                let n = nix::poll::poll(&mut polls, 20)?;
+        }
+        if event.is_reversal() {
+            cmds.extend(opts.reverse_cmd.as_deref());
+        }
+        if let SpinEvent::Cancelled(reason) = event {
+            self.stats.cancelled(reason);
+            let ev = events::Event::Cancelled {
+                reason: reason.as_str(),
+                zone: &zone.name,
+            };
+            events::emit(opts.json, ev);
+        }
 
-                if now > *touch_deadline {
-                    println!("Stopping listening touchscreen");
-                    state = State::WaitingForKeyboard;
-                    continue;
+        let Some((direction, spins)) = event.spin() else {
+            return Ok(sequence_done);
+        };
+        let ev = events::Event::Spin {
+            direction: direction.as_str(),
+            count: spins.abs(),
+            completed: sequence_done,
+            zone: &zone.name,
+        };
+        events::emit(opts.json, ev);
+        if let Some(ref cmd) = opts.level_cmd {
+            let new_level = (g.level_start + spins * opts.level_step).clamp(0, opts.level_max);
+            if new_level != self.level {
+                self.level = new_level;
+                log::info!(target: LOG_SPIN, "{}Level {}", label, new_level);
+                let level = new_level.to_string();
+                let cmd =
+                    commands::substitute(cmd, |name| (name == "level").then(|| level.clone()));
+                self.runner.run(cmd, vec![("ANDRGESTURE_LEVEL", level)])?;
+            }
+        }
+        if let Some(ref mut s) = self.state_file {
+            if opts.level_cmd.is_some() {
+                s.totals.level = Some(self.level);
+            }
+            s.record(direction);
+        }
+        if sequence_done {
+            self.stats.sequence(direction);
+        }
+        if let (true, Some(bus)) = (sequence_done, &self.bus) {
+            bus.spin_completed(direction.as_str(), spins.abs());
+        }
+        if let (true, Some(m)) = (sequence_done, &mut self.mqtt) {
+            let payload = serde_json::json!({
+                "direction": direction.as_str(),
+                "count": spins.abs(),
+                "zone": zone.name,
+            });
+            m.publish(&payload.to_string());
+        }
+        if let Some(ref mut h) = self.haptic {
+            h.buzz(sequence_done);
+        }
+        let info = commands::SpinInfo {
+            direction: direction.as_str(),
+            spins,
+            total_angle: g.spin.as_ref().map_or(0.0, |s| s.spinner * 360.0),
+            center_x: zone.center_x,
+            center_y: zone.center_y,
+            zone: &zone.name,
+        };
+        for cmd in cmds {
+            self.runner.spawn(cmd, &info)?;
+        }
+        Ok(sequence_done)
+    }
+}
+
+/// Start listening touchscreen
+fn start_listening(
+    opts: &Opts,
+    runner: &mut commands::Runner,
+    touches: &mut [Input],
+    stats: &mut stats::Stats,
+) -> Result<(), Error> {
+    log::info!(target: LOG_STATE, "Listening touchscreen");
+    events::emit(opts.json, events::Event::Armed);
+    stats.armed += 1;
+    if let Some(ref cmd) = opts.on_listen_cmd {
+        runner.spawn_plain(cmd)?;
+    }
+    if let Some(ref cmd) = opts.stream_cmd {
+        runner.start_stream(cmd)?;
+    }
+    if opts.grab {
+        set_grab(touches, true);
+    }
+    Ok(())
+}
+
+/// Go back to listening keyboard
+fn stop_listening(
+    opts: &Opts,
+    runner: &mut commands::Runner,
+    touches: &mut [Input],
+) -> Result<State, Error> {
+    log::info!(target: LOG_STATE, "Stopping listening touchscreen");
+    events::emit(opts.json, events::Event::Idle);
+    if let Some(ref cmd) = opts.on_idle_cmd {
+        runner.spawn_plain(cmd)?;
+    }
+    runner.stop_stream();
+    if opts.grab {
+        set_grab(touches, false);
+    }
+    Ok(State::WaitingForKeyboard)
+}
+
+fn main() {
+    let code = match run() {
+        Ok(()) => ExitCode::Clean,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::of(&e)
+        }
+    };
+    code.exit()
+}
+
+fn run() -> Result<(), Error> {
+    let mut opts = parse_opts();
+    // Keys selecting zone profiles arm as well
+    let zone_keys: Vec<u16> = opts.zone.iter().flat_map(|z| z.keys.clone()).collect();
+    for k in zone_keys {
+        if !opts.keycode_to_monitor.0.contains(&k) {
+            opts.keycode_to_monitor.0.push(k);
+        }
+    }
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"));
+    let level = match (opts.verbose, opts.debug) {
+        (0, false) => None,
+        (0 | 1, _) => Some(log::LevelFilter::Debug),
+        _ => Some(log::LevelFilter::Trace),
+    };
+    match opts.log_filter {
+        Some(ref categories) => {
+            logger.filter_level(log::LevelFilter::Warn);
+            for c in categories.split(',') {
+                let target = log_target(c.trim()).map_err(|e| ExitCode::Usage.wrap(e))?;
+                logger.filter_module(target, level.unwrap_or(log::LevelFilter::Info));
+            }
+        }
+        None => {
+            if let Some(level) = level {
+                logger.filter_level(level);
+            }
+        }
+    }
+    let (target, tty) = match opts.json {
+        true => (env_logger::Target::Stderr, std::io::stderr().is_terminal()),
+        false => (env_logger::Target::Stdout, std::io::stdout().is_terminal()),
+    };
+    if level.is_some() {
+        logger.format(log_format(Instant::now(), tty));
+    }
+    logger.target(target).init();
+    if opts.auto_keyboard {
+        let path = devices::find_keyboard(&opts.keycode_to_monitor.0)
+            .map_err(|e| ExitCode::DeviceOpen.wrap(e))?;
+        log::info!("Using keyboard {}", path.display());
+        opts.keybd_file = vec![path];
+    }
+    if opts.auto_touch {
+        let path = devices::find_touchscreen().map_err(|e| ExitCode::DeviceOpen.wrap(e))?;
+        log::info!("Using touchscreen {}", path.display());
+        opts.touchpad_file = vec![path];
+    }
+    install_signal_handlers()?;
+    match opts.command {
+        Some(Command::Bench(ref b)) => return bench::run(&opts, b),
+        Some(Command::Calibrate(ref c)) => return calibrate::run(&opts, c),
+        Some(Command::Monitor(ref m)) => return monitor::run(&opts, m),
+        Some(Command::Record(ref r)) => return replay::record(&opts, r),
+        Some(Command::ListDevices(_)) => {
+            devices::list(&opts.keycode_to_monitor.0);
+            return Ok(());
+        }
+        None => (),
+    }
+    check_opts(&opts).map_err(|e| ExitCode::Usage.wrap(e))?;
+    let mut replay = match opts.replay {
+        Some(ref path) => Some(replay::Replay::load(path, opts.replay_fast)?),
+        None => None,
+    };
+    let mut keybds = vec![];
+    let mut touches = vec![];
+    match (&replay, &opts.replay) {
+        (Some(r), Some(path)) => {
+            let (k, t) = r.device_counts();
+            if t == 0 {
+                return Err(ExitCode::Usage.wrap("Recording contains no touch device events"));
+            }
+            opts.keybd_file = vec![path.clone(); k];
+            opts.touchpad_file = vec![path.clone(); t];
+            keybds.extend((0..k).map(|i| Input::Replayed(replay::Source::Keyboard(i))));
+            touches.extend((0..t).map(|i| Input::Replayed(replay::Source::Touch(i))));
+        }
+        _ => {
+            let delay = Duration::from_millis(opts.open_retry_delay_ms as u64);
+            let open = |path| {
+                devices::open_with_retries(path, opts.open_retries, delay)
+                    .map_err(|e| ExitCode::DeviceOpen.wrap(e))
+            };
+            for path in &opts.keybd_file {
+                let dev = open(path)?;
+                keybds.push(Input::Live(dev));
+            }
+            for path in &opts.touchpad_file {
+                let dev = open(path)?;
+                if !opts.relative {
+                    if devices::is_touchscreen(&dev) {
+                        log::info!("{}: using ABS_MT_POSITION_X/Y", path.display());
+                    } else if devices::is_single_touch(&dev) {
+                        log::info!("{}: no multitouch, using ABS_X/ABS_Y", path.display());
+                    } else {
+                        log::warn!(
+                            "{} does not report touch positions, its touches will be ignored",
+                            path.display()
+                        );
+                    }
+                }
+                let major = evdev::AbsoluteAxisType::ABS_MT_TOUCH_MAJOR;
+                let has_major = dev
+                    .supported_absolute_axes()
+                    .is_some_and(|a| a.contains(major));
+                if opts.max_touch_major > 0 && !has_major {
+                    log::warn!(
+                        "{} does not report contact size, --max-touch-major has no effect",
+                        path.display()
+                    );
                 }
+                touches.push(Input::Live(dev));
+            }
+        }
+    }
+    let mut transforms = vec![];
+    for touch in &touches {
+        transforms.push(device_transform(&opts, touch)?);
+    }
+    apply_fractions(&mut opts, &touches[0])?;
+    let mappings = ring_mappings(&opts);
+    let mut trackers: Vec<touch::Tracker> = touches.iter().map(|_| new_tracker(&opts)).collect();
+    let empty_reads = || devices::EmptyReads {
+        count: 0,
+        max: opts.max_empty_reads,
+        action: opts.empty_reads_action,
+    };
+    let mut key_empty_reads: Vec<_> = keybds.iter().map(|_| empty_reads()).collect();
+    let mut touch_empty_reads: Vec<_> = touches.iter().map(|_| empty_reads()).collect();
+
+    let gesture_config = gesture_config(&opts).map_err(|e| ExitCode::Usage.wrap(e))?;
+    let mut zones = zones::build(&opts, &gesture_config).map_err(|e| ExitCode::Usage.wrap(e))?;
+    check_zones(&opts, &touches, &mappings, &zones)?;
+    let stats = stats::Stats::default();
+    let state_file = match opts.state_file {
+        Some(ref path) => {
+            let s = state::StateFile::load(path)?;
+            log::info!(
+                "Lifetime spins: {} clockwise, {} counterclockwise",
+                s.totals.cw_spins,
+                s.totals.ccw_spins
+            );
+            Some(s)
+        }
+        None => None,
+    };
+    let level = state_file
+        .as_ref()
+        .and_then(|s| s.totals.level)
+        .unwrap_or(opts.level_base)
+        .clamp(0, opts.level_max);
+    let mut notifier = systemd::Notifier::from_env()?;
+    let bus = match opts.dbus {
+        true => Some(dbus::Bus::connect()?),
+        false => None,
+    };
+    let mqtt = match opts.mqtt_broker {
+        Some(ref broker) => Some(mqtt::Mqtt::connect(broker, &opts.mqtt_topic)?),
+        None => None,
+    };
+    let mut control = match opts.control_socket {
+        Some(ref path) => Some(control::ControlSocket::bind(path)?),
+        None => None,
+    };
+    let haptic = match (opts.haptic, opts.dry_run) {
+        (true, false) => Some(haptic::Haptic::open(
+            &opts.haptic_sysfs,
+            opts.haptic_device.as_deref(),
+            Duration::from_millis(opts.haptic_ms as u64),
+            Duration::from_millis(opts.haptic_sequence_ms as u64),
+        )?),
+        _ => None,
+    };
+    let mut arm_fifo = match opts.arm_fifo {
+        Some(ref path) => Some(fifo::ArmFifo::open(path)?),
+        None => None,
+    };
+    let pinch_config = PinchConfig {
+        ratio: opts.pinch_ratio,
+        timeout: Duration::from_millis(opts.pinch_timeout_ms as u64),
+    };
+    let tap_config = TapConfig {
+        max_duration: Duration::from_millis(opts.tap_max_ms as u64),
+        max_movement: opts.tap_max_movement as f32,
+    };
+    let swipe_config = SwipeConfig {
+        distance: opts.swipe_distance as f32,
+        timeout: Duration::from_millis(opts.swipe_timeout_ms as u64),
+    };
+    let figure_eight_config = FigureEightConfig {
+        fraction: opts.figure_eight_fraction,
+        timeout: Duration::from_millis(opts.figure_eight_timeout_ms as u64),
+    };
 
-                if n == 0 {
+    let mut state = State::WaitingForKeyboard;
+    let mut paused = false;
+    let mut runner = new_runner(&opts);
+    if opts.cmd_timeout_ms > 0 {
+        runner.set_timeout(Duration::from_millis(opts.cmd_timeout_ms as u64));
+    }
+    if opts.max_concurrent_cmds > 0 {
+        runner.set_limit(opts.max_concurrent_cmds, opts.cmd_overflow);
+    }
+    let mut key_debounce =
+        commands::Cooldown::new(Duration::from_millis(opts.key_debounce_ms as u64));
+    let modifiers = opts
+        .modifier_keycodes
+        .as_ref()
+        .map_or(&[][..], |m| &m.0[..]);
+    let mut modifiers_down: HashSet<u16> = HashSet::new();
+    let analog = opts.analog_cmd.as_ref().map(|cmd| {
+        let interval = Duration::from_millis(opts.analog_debounce_ms as u64);
+        commands::Analog::new(
+            cmd.clone(),
+            interval,
+            opts.analog_initial,
+            opts.analog_sensitivity,
+        )
+    });
+    let progress = opts.progress_cmd.as_ref().map(|cmd| {
+        let interval = Duration::from_millis(opts.progress_interval_ms as u64);
+        commands::Throttled::new(cmd.clone(), "fraction", interval)
+    });
+    runner.enable_keys(&gesture_cmds(&opts))?;
+    let wheel = match (opts.scroll_wheel, opts.dry_run) {
+        (true, false) => Some(inject::ScrollWheel::new()?),
+        _ => None,
+    };
+    let mut dispatch = Dispatch {
+        opts: &opts,
+        attention: Attention::new(&opts),
+        transforms,
+        mappings,
+        pinch_config,
+        tap_config,
+        swipe_config,
+        figure_eight_config,
+        runner,
+        cooldown: commands::Cooldown::new(Duration::from_millis(opts.cooldown_ms as u64)),
+        stats,
+        state_file,
+        level,
+        bus,
+        mqtt,
+        haptic,
+        wheel,
+        analog,
+        progress,
+    };
+
+    if !opts.quiet {
+        summary::print(&opts, &zones);
+    }
+    if let Some(ref n) = notifier {
+        n.ready();
+    }
+    while !TERMINATE.load(Ordering::SeqCst) {
+        if replay.as_ref().is_some_and(replay::Replay::is_done) {
+            log::info!("Replay finished");
+            break;
+        }
+        let now = clock(&replay);
+        if let Some(ref mut n) = notifier {
+            n.poll();
+        }
+        dispatch.runner.reap();
+        dispatch.poll_throttled(now)?;
+        // Lost devices are reopened without blocking the loop, listening starts over then
+        let mut reopened = false;
+        for (keybd, path) in keybds.iter_mut().zip(&opts.keybd_file) {
+            if keybd.retry(path, now) {
+                modifiers_down.clear();
+                reopened = true;
+            }
+        }
+        let devs = touches.iter_mut().zip(trackers.iter_mut());
+        for ((touch, tracker), path) in devs.zip(&opts.touchpad_file) {
+            if touch.retry(path, now) {
+                *tracker = new_tracker(&opts);
+                reopened = true;
+            }
+        }
+        if reopened && matches!(state, State::WaitingForTouches(_)) {
+            state = stop_listening(&opts, &mut dispatch.runner, &mut touches)?;
+        }
+
+        let mut polls: Vec<PollFd> = keybds
+            .iter()
+            .chain(touches.iter())
+            .map(|d| PollFd::new(d.raw_fd(), PollFlags::POLLIN))
+            .collect();
+        let fifo_poll = polls.len();
+        if let Some(ref f) = arm_fifo {
+            polls.push(PollFd::new(f.raw_fd(), PollFlags::POLLIN));
+        }
+        if let Some(ref c) = control {
+            polls.extend(c.poll_fds());
+        }
+        let timeout = match state {
+            State::WaitingForKeyboard if dispatch.runner.needs_reaping() => 100,
+            State::WaitingForKeyboard => -1,
+            // Wake up in time for the deadline even with long poll intervals
+            State::WaitingForTouches(Listening {
+                deadline,
+                held: None,
+                ..
+            }) => {
+                let remaining = deadline.saturating_duration_since(now).as_millis() + 1;
+                remaining.min(opts.touch_poll_ms as u128) as i32
+            }
+            State::WaitingForTouches(_) => opts.touch_poll_ms as i32,
+        };
+        let timeout = notifier.as_ref().map_or(timeout, |n| n.timeout(timeout));
+        let retry_at = keybds.iter().chain(&touches).filter_map(Input::retry_at);
+        let wake_at = retry_at.chain(dispatch.throttled_due(now)).min();
+        let timeout = wake_at.map_or(timeout, |at| timeout_until(timeout, now, at));
+        let stnow = SystemTime::now();
+        let n = wait_input(&mut polls, timeout, &mut replay)?;
+        let now = clock(&replay);
+        let was_paused = paused;
+        if let Some(ref mut c) = control {
+            let status = state.status(now, paused, &dispatch.stats);
+            c.process(&mut zones, &mut paused, &status);
+        }
+        if TOGGLE_PAUSE.swap(false, Ordering::SeqCst) {
+            paused = !paused;
+        }
+        if paused != was_paused {
+            match paused {
+                true => log::info!(target: LOG_STATE, "Paused, ignoring arming until resumed"),
+                false => log::info!(target: LOG_STATE, "Resumed"),
+            }
+            if paused && !matches!(state, State::WaitingForKeyboard) {
+                state = stop_listening(&opts, &mut dispatch.runner, &mut touches)?;
+            }
+        }
+        let (key_polls, touch_polls) = polls.split_at(keybds.len());
+
+        let mut disarmed = false;
+        let mut key_lost = false;
+        for (i, keybd) in keybds.iter_mut().enumerate() {
+            let path = &opts.keybd_file[i];
+            let revents = key_polls[i].revents();
+            let empty = &mut key_empty_reads[i];
+            let events = match devices::read_input(keybd, path, revents, &mut replay, empty)? {
+                devices::ReadResult::Events(evs) => evs,
+                devices::ReadResult::Lost => {
+                    // Releases may have been lost along with the device
+                    modifiers_down.clear();
+                    key_lost = true;
                     continue;
                 }
-                for ev in touch.fetch_events()? {
-                    match ev.kind() {
-                        _ => (),
-                    }
+            };
+            for ev in events {
+                let evdev::InputEventKind::Key(k) = ev.kind() else {
+                    continue;
+                };
+                if ev.value() == 2 {
+                    // Autorepeat
+                    continue;
+                }
+                log::debug!(target: LOG_KEY, "Key {}", k.0);
+                if modifiers.contains(&k.0) {
+                    match ev.value() {
+                        0 => modifiers_down.remove(&k.0),
+                        _ => modifiers_down.insert(k.0),
+                    };
                 }
-                if let Some(s) = touch.cached_state().abs_vals() {
-                    let x = s[evdev::AbsoluteAxisType::ABS_MT_POSITION_X.0 as usize].value;
-                    let y = s[evdev::AbsoluteAxisType::ABS_MT_POSITION_Y.0 as usize].value;
-                    if opts.debug {
-                        println!("Touch {} {}", x, y);
+                let mut arming = false;
+                if ev.value() == 1 && opts.keycode_to_monitor.0.contains(&k.0) {
+                    if ev.timestamp().duration_since(stnow).is_err() {
+                        log::info!(target: LOG_KEY, "Stale key event");
+                    } else if modifiers.iter().any(|m| !modifiers_down.contains(m)) {
+                        log::debug!(target: LOG_KEY, "Modifiers not held");
+                    } else if !key_debounce.allow(&k.0.to_string(), clock(&replay)) {
+                        log::debug!(target: LOG_KEY, "Ignoring bouncing key {}", k.0);
+                    } else if paused {
+                        log::debug!(target: LOG_KEY, "Paused, not arming");
+                    } else {
+                        arming = true;
                     }
-                    let p: Point = point2(x, y).to_f32();
-
-                    let v = p - center;
-                    let inside_area =
-                        v.square_length() <= sqradius && v.square_length() * 64.0 > sqradius;
-
-                    if inside_area && gesture.is_none() {
-                        let a = v.angle_from_x_axis();
-                        *gesture = Some(GestureState::new(
-                            now + Duration::from_millis(opts.gesture_timeout_ms as u64),
-                            p,
-                            a,
-                        ));
+                }
+                let button_deadline = clock(&replay) + dispatch.attention.button;
+                match &mut state {
+                    State::WaitingForKeyboard if arming => {
+                        let d = &mut dispatch;
+                        start_listening(&opts, &mut d.runner, &mut touches, &mut d.stats)?;
+                        let held = opts.hold_to_listen.then_some(k.0);
+                        state = State::listening(
+                            button_deadline,
+                            held,
+                            k.0,
+                            touches.len(),
+                            zones.len(),
+                        );
                     }
-
-                    let mut remove_gesture = false;
-                    if let Some(ref mut g) = gesture {
-                        if now > g.deadline {
-                            remove_gesture = true;
+                    State::WaitingForKeyboard => (),
+                    State::WaitingForTouches(l) => {
+                        if arming {
+                            log::debug!(target: LOG_STATE, "Arming key pressed again");
+                            l.deadline = l.deadline.max(button_deadline);
+                            l.held = opts.hold_to_listen.then_some(k.0);
+                            if l.key != k.0 {
+                                // Possibly another set of zones
+                                l.key = k.0;
+                                l.gestures
+                                    .iter_mut()
+                                    .flatten()
+                                    .for_each(|g| *g = Default::default());
+                            }
                         }
-                        if (p - g.prev).square_length() > sqmaxd {
-                            remove_gesture = true;
+                        if ev.value() == 0 && l.held == Some(k.0) {
+                            log::debug!(target: LOG_KEY, "Key {} released", k.0);
+                            l.held = None;
+                            l.deadline = now + dispatch.attention.hold_grace;
                         }
-                        if inside_area {
-                            let a = v.angle_from_x_axis();
-                            let d = g.prev_angle.angle_to(a);
-                            g.deadline =
-                                now + Duration::from_millis(opts.gesture_timeout_ms as u64);
-                            g.spinner += d.radians / PI / 2.0;
-                            if opts.debug {
-                                println!("Spinner {:.1}", g.spinner);
-                            }
+                        if ev.value() == 1 && opts.disarm_keycode == Some(k.0) {
+                            log::info!(target: LOG_STATE, "Disarmed by key {}", k.0);
+                            disarmed = true;
+                        }
+                    }
+                }
+            }
+        }
+        if key_lost && matches!(state, State::WaitingForTouches(_)) {
+            state = stop_listening(&opts, &mut dispatch.runner, &mut touches)?;
+        }
+        if let Some(ref mut f) = arm_fifo {
+            if f.take(polls[fifo_poll].revents()) && !paused {
+                let deadline = now + dispatch.attention.button;
+                match &mut state {
+                    State::WaitingForKeyboard => {
+                        log::debug!(target: LOG_KEY, "Armed through FIFO");
+                        let d = &mut dispatch;
+                        start_listening(&opts, &mut d.runner, &mut touches, &mut d.stats)?;
+                        // No arming key, so only zones without `key=` are active
+                        state = State::listening(deadline, None, 0, touches.len(), zones.len());
+                    }
+                    State::WaitingForTouches(l) => l.deadline = l.deadline.max(deadline),
+                }
+            }
+        }
 
-                            let mut react_cw = false;
-                            let mut react_ccw = false;
-                            if g.reacted_spin > 0.5 {
-                                if g.spinner >= g.reacted_spin + 1.0 {
-                                    g.reacted_spin += 1.0;
-                                    react_cw = true;
-                                } else if g.spinner < g.reacted_spin - 1.0 {
-                                    println!("Spinned in the opposite direction");
-                                    remove_gesture = true;
-                                }
-                            } else if g.reacted_spin < -0.5 {
-                                if g.spinner <= g.reacted_spin - 1.0 {
-                                    g.reacted_spin -= 1.0;
-                                    react_ccw = true;
-                                } else if g.spinner > g.reacted_spin + 1.0 {
-                                    println!("Spinned in the opposite direction");
-                                    remove_gesture = true;
-                                }
-                            } else {
-                                if g.spinner >= g.reacted_spin + 1.0 {
-                                    g.reacted_spin += 1.0;
-                                    react_cw = true;
-                                } else if g.spinner < g.reacted_spin - 1.0 {
-                                    g.reacted_spin -= 1.0;
-                                    react_ccw = true;
-                                }
-                            }
+        let State::WaitingForTouches(listening) = &mut state else {
+            // Keep contact tracking up to date for when listening starts
+            let mut triggered = false;
+            for (i, (touch, tracker)) in touches.iter_mut().zip(trackers.iter_mut()).enumerate() {
+                let path = &opts.touchpad_file[i];
+                let revents = touch_polls[i].revents();
+                let empty = &mut touch_empty_reads[i];
+                let events = match devices::read_input(touch, path, revents, &mut replay, empty)? {
+                    devices::ReadResult::Events(evs) => evs,
+                    devices::ReadResult::Lost => {
+                        *tracker = new_tracker(&opts);
+                        continue;
+                    }
+                };
+                for ev in events {
+                    tracker.process(&ev);
+                    if let (Some(code), evdev::InputEventKind::Key(k)) =
+                        (opts.trigger_button, ev.kind())
+                    {
+                        triggered |= k.0 == code
+                            && ev.value() == 1
+                            && key_debounce.allow(&k.0.to_string(), now);
+                    }
+                }
+            }
+            if let (true, false, Some(code)) = (triggered, paused, opts.trigger_button) {
+                let deadline = now + dispatch.attention.button;
+                let d = &mut dispatch;
+                start_listening(&opts, &mut d.runner, &mut touches, &mut d.stats)?;
+                state = State::listening(deadline, None, code, touches.len(), zones.len());
+            }
+            continue;
+        };
 
-                            if react_ccw || react_cw {
-                                *touch_deadline = now + Duration::from_millis(opts.after_spin_attention_time_ms as u64);
-                            }
+        if disarmed || (listening.held.is_none() && now > listening.deadline) {
+            state = stop_listening(&opts, &mut dispatch.runner, &mut touches)?;
+            continue;
+        }
 
-                            let ctr : i32 = g.reacted_spin as i32;
-                            let mut cmdline : Option<&str> = None;
-                            if react_cw {
-                                if ctr >= opts.cw_spins_required as i32 {
-                                    *touch_deadline = now + Duration::from_millis(opts.after_successful_cw_spin_sequence_attention_time as u64);
-                                    println!("SPIN CW {} !", ctr);
-                                    cmdline = Some(opts.cmdline_for_cw_spins.as_ref());
-                                } else {
-                                    println!("SPIN CW {}", ctr);
-                                }
-                            }
-                            if react_ccw {
-                                if - ctr >= opts.ccw_spins_required as i32 {
-                                    println!("SPIN CCW {} !", ctr);
-                                    cmdline = Some(opts.cmdline_for_ccw_spins.as_ref());
-                                } else {
-                                    println!("SPIN CCW {}", ctr);
-                                }
-                            }
+        if n == 0 {
+            continue;
+        }
 
-                            if let Some(cmd) = cmdline {
-                                std::process::Command::new("sh").arg("-c").arg(cmd).spawn()?;
-                            }
+        let mut winner = None;
+        let mut lost = false;
+        let mut cancelled = false;
+        let devs = touches.iter_mut().zip(trackers.iter_mut());
+        for (i, (touch, tracker)) in devs.enumerate() {
+            let path = &opts.touchpad_file[i];
+            let revents = touch_polls[i].revents();
+            let empty = &mut touch_empty_reads[i];
+            let events = match devices::read_input(touch, path, revents, &mut replay, empty)? {
+                devices::ReadResult::Events(evs) => evs,
+                devices::ReadResult::Lost => {
+                    *tracker = new_tracker(&opts);
+                    lost = true;
+                    break;
+                }
+            };
+            // The whole batch goes to the tracker so that no lift or frame end gets lost,
+            // gestures only see frames until listening is cancelled or a sequence completes
+            for ev in events {
+                // Positions are evaluated on complete frames
+                let Some(lifted) = tracker.process(&ev) else {
+                    continue;
+                };
+                if cancelled || winner.is_some() {
+                    continue;
+                }
+                match dispatch.frame(&zones, listening, i, tracker, lifted, now)? {
+                    Outcome::Continue => (),
+                    Outcome::Cancelled => cancelled = true,
+                    Outcome::Completed(j) => winner = Some((i, j)),
+                }
+            }
+        }
 
-                            g.prev_angle = a;
-                        }
+        if cancelled {
+            if let Some(ref cmd) = opts.cancel_cmd {
+                dispatch.runner.spawn_plain(cmd)?;
+            }
+        }
+        if lost || cancelled {
+            state = stop_listening(&opts, &mut dispatch.runner, &mut touches)?;
+            continue;
+        }
 
-                        g.prev = p;
-                    }
-                    if remove_gesture {
-                        *gesture = None;
-                    }
-                } else {
-                    if opts.debug {
-                        println!("No absvals");
+        // The first device and zone to complete the spin sequence wins, others start over
+        if let Some(w) = winner {
+            for (i, dev_gestures) in listening.gestures.iter_mut().enumerate() {
+                for (j, zone_gestures) in dev_gestures.iter_mut().enumerate() {
+                    if (i, j) != w {
+                        zone_gestures.spin = None;
                     }
                 }
             }
         }
     }
+
+    if let Some(ref n) = notifier {
+        n.stopping();
+    }
+    if opts.grab && matches!(state, State::WaitingForTouches(_)) {
+        set_grab(&mut touches, false);
+    }
+    dispatch.runner.stop_stream();
+    dispatch.stats.report(opts.stats_file.as_deref())?;
+    log::info!("Exiting");
+    Ok(())
 }
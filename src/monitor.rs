@@ -0,0 +1,124 @@
+use std::{io::IsTerminal, os::unix::prelude::AsRawFd, sync::atomic::Ordering};
+
+use andrgesture::{ring_angle, Angle, Point};
+use euclid::point2;
+use evdev::{EventType, InputEventKind, Synchronization};
+use gumdrop::Options;
+use nix::poll::{PollFd, PollFlags};
+
+use crate::{devices, new_tracker, poll, Error, Opts, TERMINATE};
+
+/// Dump raw events of the touch device, without waiting for the key press
+#[derive(Options)]
+pub struct MonitorOpts {
+    help: bool,
+    /// Show only events of this type: abs, key, rel, syn or msc
+    #[options(short = 'f')]
+    filter: Option<String>,
+    /// Draw the ring and the touch point instead of dumping events, updating in place on a terminal
+    #[options(short = 'r')]
+    ring: bool,
+}
+
+/// Size of the `--ring` picture in characters. Terminal cells are about twice as tall as wide.
+const WIDTH: usize = 61;
+const HEIGHT: usize = 31;
+
+/// ASCII picture of the ring (`.` edges, `+` center) with the touch point `@`
+fn draw(p: Option<Point>, center: Point, radius: f32, inner_radius: f32) -> String {
+    // Some margin around the ring, so that points just outside are visible
+    let extent = radius * 1.25;
+    let cell = 2.0 * extent / HEIGHT as f32;
+    let cell_of = |q: Point| {
+        let col = ((q.x - center.x) / extent + 1.0) * WIDTH as f32 / 2.0;
+        let row = ((q.y - center.y) / extent + 1.0) * HEIGHT as f32 / 2.0;
+        let visible = (0.0..WIDTH as f32).contains(&col) && (0.0..HEIGHT as f32).contains(&row);
+        visible.then_some((col as usize, row as usize))
+    };
+    let touched = p.and_then(cell_of);
+    let mut out = String::with_capacity((WIDTH + 1) * HEIGHT);
+    for row in 0..HEIGHT {
+        for col in 0..WIDTH {
+            let q = point2(
+                center.x + ((col as f32 + 0.5) * 2.0 / WIDTH as f32 - 1.0) * extent,
+                center.y + ((row as f32 + 0.5) * 2.0 / HEIGHT as f32 - 1.0) * extent,
+            );
+            let d = (q - center).length();
+            let c = if touched == Some((col, row)) {
+                '@'
+            } else if (col, row) == (WIDTH / 2, HEIGHT / 2) {
+                '+'
+            } else if (d - radius).abs() < cell / 2.0 || (d - inner_radius).abs() < cell / 2.0 {
+                '.'
+            } else {
+                ' '
+            };
+            out.push(c);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn parse_filter(s: &str) -> Result<EventType, Error> {
+    Ok(match s {
+        "abs" => EventType::ABSOLUTE,
+        "key" => EventType::KEY,
+        "rel" => EventType::RELATIVE,
+        "syn" => EventType::SYNCHRONIZATION,
+        "msc" => EventType::MISC,
+        _ => return Err(format!("Unknown event type filter: {}", s).into()),
+    })
+}
+
+pub fn run(opts: &Opts, mopts: &MonitorOpts) -> Result<(), Error> {
+    let filter = mopts.filter.as_deref().map(parse_filter).transpose()?;
+    let mut touch = devices::open_blocking(&opts.touchpad_file[0])?;
+    let mut tracker = new_tracker(opts);
+    let center: Point = point2(opts.center_x, opts.center_y).to_f32();
+    let radius = opts.radius as f32;
+    let inner_radius = opts.inner_radius.map_or(radius / 8.0, |r| r as f32);
+    // Without a terminal, just the point lines are printed
+    let picture = mopts.ring && std::io::stdout().is_terminal();
+    if picture {
+        // Clear the screen once, then redraw from the top left corner
+        print!("\x1b[2J\x1b[H{}", draw(None, center, radius, inner_radius));
+    }
+
+    while !TERMINATE.load(Ordering::SeqCst) {
+        let mut polls = [PollFd::new(touch.as_raw_fd(), PollFlags::POLLIN)];
+        if poll(&mut polls, -1)? == 0 {
+            continue;
+        }
+        for ev in touch.fetch_events()? {
+            tracker.process(&ev);
+            if !mopts.ring && filter.is_none_or(|f| f == ev.event_type()) {
+                println!("{:?} code={} value={}", ev.kind(), ev.code(), ev.value());
+            }
+            if ev.kind() == InputEventKind::Synchronization(Synchronization::SYN_REPORT) {
+                let p = tracker.point();
+                if picture {
+                    print!("\x1b[H{}", draw(p, center, radius, inner_radius));
+                }
+                if let Some(p) = p {
+                    let v = p - center;
+                    let d = v.length();
+                    let inside = d <= radius && d > inner_radius;
+                    println!(
+                        "  point {} {}  distance {:.0}  angle {:.1}  {}{}",
+                        p.x,
+                        p.y,
+                        d,
+                        ring_angle(v, Angle::degrees(opts.angle_origin)).to_degrees(),
+                        if inside { "inside" } else { "outside" },
+                        // Erase leftovers of a longer previous line
+                        if picture { "\x1b[K" } else { "" }
+                    );
+                } else if picture {
+                    println!("  no touch\x1b[K");
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,357 @@
+//! Publishing completed spin sequences to an MQTT broker, available with the `mqtt` cargo feature.
+//! Only what's needed for QoS 0 publishing of MQTT 3.1.1 is implemented, over a background thread
+//! so that an unreachable broker never stalls gesture handling.
+
+use crate::Error;
+
+#[cfg(feature = "mqtt")]
+use std::{
+    io::{ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError},
+    time::{Duration, Instant},
+};
+
+/// Limits how long the publishing thread waits for an unreachable broker
+#[cfg(feature = "mqtt")]
+const TIMEOUT: Duration = Duration::from_secs(2);
+#[cfg(feature = "mqtt")]
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(10);
+/// Asked of the broker on connecting, pings are sent when nothing was published for half of it
+#[cfg(feature = "mqtt")]
+const KEEP_ALIVE: Duration = Duration::from_secs(60);
+/// Messages waiting for the publishing thread, newer ones are dropped beyond that
+#[cfg(feature = "mqtt")]
+const QUEUE: usize = 64;
+
+/// Handle queueing messages for the publishing thread, which exits when it's dropped
+#[cfg(feature = "mqtt")]
+pub struct Mqtt {
+    broker: String,
+    queue: SyncSender<String>,
+}
+
+/// Broker connection owned by the publishing thread
+#[cfg(feature = "mqtt")]
+struct Connection {
+    /// `HOST:PORT`
+    broker: String,
+    topic: String,
+    stream: Option<TcpStream>,
+    /// Last failed connection attempt, to avoid waiting on each message while the broker is down
+    failed_at: Option<Instant>,
+}
+
+/// Append MQTT variable length integer
+#[cfg(feature = "mqtt")]
+fn push_length(packet: &mut Vec<u8>, mut len: usize) {
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        packet.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+}
+
+/// Read MQTT variable length integer
+#[cfg(feature = "mqtt")]
+fn read_length(r: &mut impl Read) -> Result<usize, Error> {
+    let mut len = 0;
+    for i in 0..4 {
+        let mut byte = [0u8];
+        r.read_exact(&mut byte)?;
+        len += ((byte[0] & 0x7f) as usize) << (7 * i);
+        if byte[0] & 0x80 == 0 {
+            return Ok(len);
+        }
+    }
+    Err("Malformed remaining length".into())
+}
+
+/// Append length-prefixed UTF-8 string, which can't be longer than `u16::MAX` bytes
+#[cfg(feature = "mqtt")]
+fn push_str(packet: &mut Vec<u8>, s: &str) -> Result<(), Error> {
+    let len = u16::try_from(s.len()).map_err(|_| format!("{} bytes is too long", s.len()))?;
+    packet.extend_from_slice(&len.to_be_bytes());
+    packet.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+/// Read CONNACK from the broker, failing if it refused the connection
+#[cfg(feature = "mqtt")]
+fn read_connack(r: &mut impl Read) -> Result<(), Error> {
+    let mut kind = [0u8];
+    r.read_exact(&mut kind)?;
+    let mut body = vec![0u8; read_length(r)?];
+    r.read_exact(&mut body)?;
+    let code = match (kind[0], &body[..]) {
+        (0x20, [_, code]) => *code,
+        _ => return Err(format!("Expected CONNACK, got packet type {:#04x}", kind[0]).into()),
+    };
+    let reason = match code {
+        0 => return Ok(()),
+        1 => "unacceptable protocol version",
+        2 => "client identifier rejected",
+        3 => "server unavailable",
+        4 => "bad user name or password",
+        5 => "not authorized",
+        _ => "unknown reason",
+    };
+    Err(format!("Connection refused with code {} ({})", code, reason).into())
+}
+
+/// Packet with the fixed header
+#[cfg(feature = "mqtt")]
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut p = vec![kind];
+    push_length(&mut p, body.len());
+    p.extend_from_slice(body);
+    p
+}
+
+#[cfg(feature = "mqtt")]
+impl Mqtt {
+    /// Start the publishing thread, which connects to the broker.
+    /// Failures are only logged, connecting is retried on publishing.
+    pub fn connect(broker: &str, topic: &str) -> Result<Mqtt, Error> {
+        if topic.len() > u16::MAX as usize {
+            return Err(format!("MQTT topic can't be longer than {} bytes", u16::MAX).into());
+        }
+        let broker = match broker.rsplit_once(':') {
+            Some(_) => broker.to_owned(),
+            None => format!("{}:1883", broker),
+        };
+        let mut conn = Connection {
+            broker: broker.clone(),
+            topic: topic.to_owned(),
+            stream: None,
+            failed_at: None,
+        };
+        let (queue, messages) = mpsc::sync_channel::<String>(QUEUE);
+        std::thread::Builder::new()
+            .name("mqtt".to_owned())
+            .spawn(move || {
+                conn.reconnect();
+                loop {
+                    match messages.recv_timeout(KEEP_ALIVE / 2) {
+                        Ok(payload) => conn.publish(&payload),
+                        Err(RecvTimeoutError::Timeout) => conn.ping(),
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            })?;
+        Ok(Mqtt { broker, queue })
+    }
+
+    /// Queue for publishing on the topic with QoS 0, without waiting for the broker
+    pub fn publish(&mut self, payload: &str) {
+        if payload.len() > u16::MAX as usize {
+            log::warn!(
+                "MQTT message of {} bytes is too long, dropping it",
+                payload.len()
+            );
+            return;
+        }
+        match self.queue.try_send(payload.to_owned()) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => log::warn!(
+                "MQTT broker {} is too slow, dropping a message",
+                self.broker
+            ),
+            Err(TrySendError::Disconnected(_)) => {
+                log::warn!("MQTT publishing to {} stopped", self.broker)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "mqtt")]
+impl Connection {
+    /// Connect unless the previous attempt failed recently. Returns whether connected.
+    fn reconnect(&mut self) -> bool {
+        self.stream = None;
+        if self
+            .failed_at
+            .is_some_and(|t| t.elapsed() < RECONNECT_INTERVAL)
+        {
+            return false;
+        }
+        match self.open() {
+            Ok(s) => {
+                log::info!("Connected to MQTT broker {}", self.broker);
+                self.stream = Some(s);
+                self.failed_at = None;
+                true
+            }
+            Err(e) => {
+                log::warn!("Failed to connect to MQTT broker {}: {}", self.broker, e);
+                self.failed_at = Some(Instant::now());
+                false
+            }
+        }
+    }
+
+    fn open(&self) -> Result<TcpStream, Error> {
+        let addr = self
+            .broker
+            .to_socket_addrs()?
+            .next()
+            .ok_or("No address for the broker")?;
+        let mut s = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        s.set_read_timeout(Some(TIMEOUT))?;
+        s.set_write_timeout(Some(TIMEOUT))?;
+        let mut body = vec![];
+        push_str(&mut body, "MQTT")?;
+        // Protocol level 4 (3.1.1), clean session
+        body.extend_from_slice(&[4, 0x02]);
+        body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16).to_be_bytes());
+        push_str(&mut body, &format!("andrgesture-{}", std::process::id()))?;
+        s.write_all(&packet(0x10, &body))?;
+        read_connack(&mut s)?;
+        Ok(s)
+    }
+
+    /// Whether the broker closed the connection, checked without blocking
+    fn closed(mut s: &TcpStream) -> bool {
+        let mut buf = [0u8; 64];
+        if s.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let closed = loop {
+            match s.read(&mut buf) {
+                Ok(0) => break true,
+                // Only PINGRESP is expected from the broker for QoS 0, skip it
+                Ok(_) => (),
+                Err(e) => break e.kind() != ErrorKind::WouldBlock,
+            }
+        };
+        closed || s.set_nonblocking(false).is_err()
+    }
+
+    /// Send PINGREQ to keep the connection alive, dropping it on failure to reconnect on publishing
+    fn ping(&mut self) {
+        let Some(s) = self.stream.as_mut() else {
+            return;
+        };
+        if let Err(e) = s.write_all(&packet(0xc0, &[])) {
+            log::debug!("Failed to ping MQTT broker {}: {}", self.broker, e);
+            self.stream = None;
+        }
+    }
+
+    fn send(&mut self, payload: &str) -> Result<(), Error> {
+        let s = self.stream.as_mut().ok_or("Not connected")?;
+        let mut body = vec![];
+        push_str(&mut body, &self.topic)?;
+        body.extend_from_slice(payload.as_bytes());
+        s.write_all(&packet(0x30, &body))?;
+        Ok(())
+    }
+
+    /// Publish on the topic with QoS 0, reconnecting if the connection got lost
+    fn publish(&mut self, payload: &str) {
+        if self.stream.as_ref().is_none_or(Connection::closed) && !self.reconnect() {
+            log::warn!(
+                "Not connected to MQTT broker {}, dropping a message",
+                self.broker
+            );
+            return;
+        }
+        // Broken connection may show up only on writing, try once more over a new one.
+        // `failed_at` is clear while connected, so this doesn't wait for `RECONNECT_INTERVAL`.
+        let result = match self.send(payload) {
+            Err(_) if self.reconnect() => self.send(payload),
+            r => r,
+        };
+        if let Err(e) = result {
+            log::warn!("Failed to publish to MQTT broker {}: {}", self.broker, e);
+            self.stream = None;
+        }
+    }
+}
+
+/// Can't be constructed without MQTT support
+#[cfg(not(feature = "mqtt"))]
+pub enum Mqtt {}
+
+#[cfg(not(feature = "mqtt"))]
+impl Mqtt {
+    pub fn connect(_broker: &str, _topic: &str) -> Result<Mqtt, Error> {
+        Err("Built without MQTT support, enable the `mqtt` cargo feature".into())
+    }
+
+    pub fn publish(&mut self, _payload: &str) {
+        match *self {}
+    }
+}
+
+#[cfg(all(test, feature = "mqtt"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lengths() {
+        for len in [0, 127, 128, 16_383, 16_384, 2_097_152] {
+            let mut p = vec![];
+            push_length(&mut p, len);
+            assert_eq!(read_length(&mut &p[..]).unwrap(), len);
+        }
+        assert!(read_length(&mut &[0xff, 0xff, 0xff, 0xff, 0x01][..]).is_err());
+    }
+
+    #[test]
+    fn long_strings_are_rejected() {
+        let mut p = vec![];
+        push_str(&mut p, &"a".repeat(u16::MAX as usize)).unwrap();
+        assert_eq!(p.len(), 2 + u16::MAX as usize);
+        assert!(push_str(&mut p, &"a".repeat(u16::MAX as usize + 1)).is_err());
+        assert!(Mqtt::connect("localhost", &"a".repeat(u16::MAX as usize + 1)).is_err());
+    }
+
+    #[test]
+    fn connack() {
+        assert!(read_connack(&mut &[0x20, 2, 0, 0][..]).is_ok());
+        let refused = read_connack(&mut &[0x20, 2, 0, 5][..]).unwrap_err();
+        assert_eq!(
+            refused.to_string(),
+            "Connection refused with code 5 (not authorized)"
+        );
+        assert!(read_connack(&mut &[0xd0, 0][..]).is_err());
+        assert!(read_connack(&mut &[0x20, 3, 0, 0, 0][..]).is_err());
+        assert!(read_connack(&mut &[0x20, 2, 0][..]).is_err());
+    }
+
+    #[test]
+    fn publish_and_ping() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut conn = Connection {
+            broker: listener.local_addr().unwrap().to_string(),
+            topic: "t".to_owned(),
+            stream: None,
+            failed_at: None,
+        };
+        let broker = std::thread::spawn(move || {
+            let (mut s, _) = listener.accept().unwrap();
+            let mut header = [0u8; 2];
+            s.read_exact(&mut header).unwrap();
+            let mut connect = vec![0u8; header[1] as usize];
+            s.read_exact(&mut connect).unwrap();
+            s.write_all(&[0x20, 2, 0, 0]).unwrap();
+            let mut rest = [0u8; 8];
+            s.read_exact(&mut rest).unwrap();
+            (connect, rest)
+        });
+        assert!(conn.reconnect());
+        conn.publish("x");
+        conn.ping();
+        let (connect, rest) = broker.join().unwrap();
+        // Keep alive of 60 seconds after the protocol level and flags
+        assert_eq!(connect[..10], [0, 4, b'M', b'Q', b'T', b'T', 4, 2, 0, 60]);
+        assert_eq!(rest, [0x30, 4, 0, 1, b't', b'x', 0xc0, 0]);
+    }
+}
@@ -0,0 +1,227 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+
+use evdev::{EventType, InputEvent};
+use gumdrop::Options;
+use nix::poll::{PollFd, PollFlags};
+
+use crate::{devices, poll, Error, Opts, TERMINATE};
+
+/// Save events of keyboard and touch devices to a file for `--replay`, until interrupted
+#[derive(Options)]
+pub struct RecordOpts {
+    help: bool,
+    /// File to write, one event per line: `MICROSECONDS k|tINDEX TYPE CODE VALUE`
+    #[options(short = 'o', required)]
+    output: PathBuf,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Source {
+    Keyboard(usize),
+    Touch(usize),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Source::Keyboard(i) => write!(f, "k{}", i),
+            Source::Touch(i) => write!(f, "t{}", i),
+        }
+    }
+}
+
+impl std::str::FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match (s.get(..1), s.get(1..)) {
+            (Some("k"), Some(i)) => Ok(Source::Keyboard(i.parse()?)),
+            (Some("t"), Some(i)) => Ok(Source::Touch(i.parse()?)),
+            _ => Err(format!("Invalid event source `{}`", s).into()),
+        }
+    }
+}
+
+pub fn record(opts: &Opts, ropts: &RecordOpts) -> Result<(), Error> {
+    let mut devs = vec![];
+    for (i, path) in opts.keybd_file.iter().enumerate() {
+        devs.push((Source::Keyboard(i), devices::open(path)?));
+    }
+    for (i, path) in opts.touchpad_file.iter().enumerate() {
+        devs.push((Source::Touch(i), devices::open(path)?));
+    }
+    let mut out = BufWriter::new(File::create(&ropts.output)?);
+    log::info!("Recording to {}, interrupt to stop", ropts.output.display());
+    let start = Instant::now();
+    let mut count = 0;
+    while !TERMINATE.load(Ordering::SeqCst) {
+        let mut polls: Vec<PollFd> = devs
+            .iter()
+            .map(|(_, d)| PollFd::new(std::os::unix::io::AsRawFd::as_raw_fd(d), PollFlags::POLLIN))
+            .collect();
+        if poll(&mut polls, -1)? == 0 {
+            continue;
+        }
+        let offset = start.elapsed().as_micros();
+        for (i, (source, dev)) in devs.iter_mut().enumerate() {
+            if !polls[i]
+                .revents()
+                .is_some_and(|r| r.contains(PollFlags::POLLIN))
+            {
+                continue;
+            }
+            let events = match dev.fetch_events() {
+                Ok(evs) => evs.collect::<Vec<_>>(),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for ev in events {
+                let t = ev.event_type().0;
+                writeln!(
+                    out,
+                    "{} {} {} {} {}",
+                    offset,
+                    source,
+                    t,
+                    ev.code(),
+                    ev.value()
+                )?;
+                count += 1;
+            }
+        }
+        out.flush()?;
+    }
+    log::info!("Recorded {} events", count);
+    Ok(())
+}
+
+struct Record {
+    /// Time since start of the recording
+    offset: Duration,
+    source: Source,
+    /// Type, code and value. Replayed events get timestamp of the moment they're taken.
+    event: (u16, u16, i32),
+}
+
+/// Events loaded from a `record` file, handed out according to their original timing
+pub struct Replay {
+    records: VecDeque<Record>,
+    /// Due events not taken yet
+    pending: Vec<Record>,
+    start: Instant,
+    /// Don't sleep, advance a virtual clock instead
+    fast: bool,
+    clock: Instant,
+    /// Recording ended while waiting without a timeout, so nothing else is going to happen
+    drained: bool,
+}
+
+impl Replay {
+    pub fn load(path: &Path, fast: bool) -> Result<Replay, Error> {
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open replay file {}: {}", path.display(), e))?;
+        let mut records = VecDeque::new();
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let r =
+                parse_record(&line).map_err(|e| format!("{}:{}: {}", path.display(), n + 1, e))?;
+            records.push_back(r);
+        }
+        let start = Instant::now();
+        Ok(Replay {
+            records,
+            pending: vec![],
+            start,
+            fast,
+            clock: start,
+            drained: false,
+        })
+    }
+
+    /// Number of keyboard and touch devices in the recording
+    pub fn device_counts(&self) -> (usize, usize) {
+        let mut counts = (0, 0);
+        for r in &self.records {
+            match r.source {
+                Source::Keyboard(i) => counts.0 = counts.0.max(i + 1),
+                Source::Touch(i) => counts.1 = counts.1.max(i + 1),
+            }
+        }
+        counts
+    }
+
+    pub fn now(&self) -> Instant {
+        match self.fast {
+            true => self.clock,
+            false => Instant::now(),
+        }
+    }
+
+    /// Wait until the next event is due or `timeout_ms` passes (-1 for no timeout), like `poll`
+    pub fn wait(&mut self, timeout_ms: i32) -> Result<(), Error> {
+        let next = self.records.front().map(|r| self.start + r.offset);
+        let now = self.now();
+        let until = match (next, timeout_ms) {
+            (Some(next), t) if t >= 0 => next.max(now).min(now + Duration::from_millis(t as u64)),
+            (Some(next), _) => next.max(now),
+            (None, t) if t >= 0 => now + Duration::from_millis(t as u64),
+            (None, _) => {
+                self.drained = true;
+                return Ok(());
+            }
+        };
+        match self.fast {
+            true => self.clock = until,
+            false => {
+                let ms = until.saturating_duration_since(now).as_millis();
+                poll(&mut [], ms as _)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Take due events of the device
+    pub fn take(&mut self, source: Source) -> Vec<InputEvent> {
+        let now = self.now();
+        while let Some(r) = self.records.front() {
+            if self.start + r.offset > now {
+                break;
+            }
+            self.pending.extend(self.records.pop_front());
+        }
+        let (taken, rest): (Vec<Record>, _) = std::mem::take(&mut self.pending)
+            .into_iter()
+            .partition(|r| r.source == source);
+        self.pending = rest;
+        taken
+            .into_iter()
+            .map(|r| InputEvent::new_now(EventType(r.event.0), r.event.1, r.event.2))
+            .collect()
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.records.is_empty() && (self.pending.is_empty() || self.drained)
+    }
+}
+
+fn parse_record(line: &str) -> Result<Record, Error> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [offset, source, t, code, value] = fields[..] else {
+        return Err("Expected 5 fields".into());
+    };
+    Ok(Record {
+        offset: Duration::from_micros(offset.parse()?),
+        source: source.parse()?,
+        event: (t.parse()?, code.parse()?, value.parse()?),
+    })
+}
@@ -0,0 +1,67 @@
+use std::{
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use andrgesture::Direction;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+
+/// Lifetime spin counts and the `--level-cmd` level, kept in `--state-file`
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Totals {
+    pub cw_spins: u64,
+    pub ccw_spins: u64,
+    pub level: Option<i32>,
+}
+
+pub struct StateFile {
+    path: PathBuf,
+    pub totals: Totals,
+}
+
+impl StateFile {
+    /// Load counts from `path`, starting from zero if the file does not exist yet
+    pub fn load(path: &Path) -> Result<StateFile, Error> {
+        let totals = match std::fs::read_to_string(path) {
+            Ok(s) => toml::from_str(&s)
+                .map_err(|e| format!("Invalid state file {}: {}", path.display(), e))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Totals::default(),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e).into()),
+        };
+        Ok(StateFile {
+            path: path.to_owned(),
+            totals,
+        })
+    }
+
+    /// Count one more spin and save the file
+    pub fn record(&mut self, direction: Direction) {
+        match direction {
+            Direction::Cw => self.totals.cw_spins += 1,
+            Direction::Ccw => self.totals.ccw_spins += 1,
+        }
+        if let Err(e) = self.save() {
+            log::warn!("Failed to save {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Write to a temporary file and rename it over the old one, so that the file is never partially written
+    fn save(&self) -> Result<(), Error> {
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        let mut f = std::fs::File::create(&tmp)?;
+        f.write_all(toml::to_string(&self.totals)?.as_bytes())?;
+        f.sync_all()?;
+        std::fs::rename(&tmp, &self.path)?;
+        let dir = match self.path.parent() {
+            Some(d) if !d.as_os_str().is_empty() => d,
+            _ => Path::new("."),
+        };
+        std::fs::File::open(dir)?.sync_all()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use andrgesture::{CancelReason, Direction};
+use serde::Serialize;
+
+use crate::Error;
+
+/// Counters reported on exit, for tuning thresholds
+#[derive(Serialize, Default)]
+pub struct Stats {
+    pub armed: u64,
+    pub cw_sequences: u64,
+    pub ccw_sequences: u64,
+    pub cancelled_timeout: u64,
+    pub cancelled_jump: u64,
+    pub cancelled_opposite_direction: u64,
+    pub cancelled_max_duration: u64,
+}
+
+impl Stats {
+    pub fn sequence(&mut self, direction: Direction) {
+        match direction {
+            Direction::Cw => self.cw_sequences += 1,
+            Direction::Ccw => self.ccw_sequences += 1,
+        }
+    }
+
+    pub fn cancelled(&mut self, reason: CancelReason) {
+        match reason {
+            CancelReason::Timeout => self.cancelled_timeout += 1,
+            CancelReason::Jump => self.cancelled_jump += 1,
+            CancelReason::OppositeDirection => self.cancelled_opposite_direction += 1,
+            CancelReason::MaxDuration => self.cancelled_max_duration += 1,
+        }
+    }
+
+    /// Log the summary, also writing it to `path` as TOML if specified
+    pub fn report(&self, path: Option<&Path>) -> Result<(), Error> {
+        let text = toml::to_string(self)?;
+        log::info!("Statistics:\n{}", text.trim_end());
+        if let Some(path) = path {
+            std::fs::write(path, text)
+                .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
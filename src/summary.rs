@@ -0,0 +1,128 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{events, zones::Zone, GestureType, Opts};
+
+#[derive(Serialize)]
+pub struct ZoneSummary<'a> {
+    name: &'a str,
+    center_x: i32,
+    center_y: i32,
+    radius: f32,
+    inner_radius: f32,
+    cw_spins_required: usize,
+    ccw_spins_required: usize,
+    cw_cmd: Option<&'a str>,
+    ccw_cmd: Option<&'a str>,
+    /// Arming keys the zone is active for, any if empty
+    keys: &'a [u16],
+}
+
+/// Effective configuration after merging the config file, command line and device properties
+#[derive(Serialize)]
+pub struct Summary<'a> {
+    keyboards: &'a [PathBuf],
+    touch_devices: &'a [PathBuf],
+    keycodes: &'a [u16],
+    trigger_button: Option<u16>,
+    arm_fifo: Option<&'a PathBuf>,
+    gesture_type: GestureType,
+    cw_spin_fraction: f32,
+    ccw_spin_fraction: f32,
+    gesture_timeout_ms: u32,
+    max_jump_distance: u32,
+    max_jump_velocity: Option<u32>,
+    jump_samples: u32,
+    min_samples_per_spin: u32,
+    min_quadrants: u32,
+    min_angular_velocity: f32,
+    smoothing: f32,
+    after_buttonpress_attention_time_ms: u32,
+    after_spin_attention_time_ms: u32,
+    cmd_dir: Option<&'a PathBuf>,
+    dry_run: bool,
+    /// Commands besides the zone ones, by option name
+    commands: BTreeMap<&'static str, &'a str>,
+    zones: Vec<ZoneSummary<'a>>,
+}
+
+impl<'a> Summary<'a> {
+    pub fn new(opts: &'a Opts, zones: &'a [Zone]) -> Summary<'a> {
+        let commands = [
+            ("per_spin_cw_cmd", &opts.per_spin_cw_cmd),
+            ("per_spin_ccw_cmd", &opts.per_spin_ccw_cmd),
+            ("cw_sequence_cmd", &opts.cw_sequence_cmd),
+            ("ccw_sequence_cmd", &opts.ccw_sequence_cmd),
+            ("reverse_cmd", &opts.reverse_cmd),
+            ("cancel_cmd", &opts.cancel_cmd),
+            ("on_listen_cmd", &opts.on_listen_cmd),
+            ("on_idle_cmd", &opts.on_idle_cmd),
+            ("swipe_up_cmd", &opts.swipe_up_cmd),
+            ("swipe_down_cmd", &opts.swipe_down_cmd),
+            ("swipe_left_cmd", &opts.swipe_left_cmd),
+            ("swipe_right_cmd", &opts.swipe_right_cmd),
+            ("pinch_in_cmd", &opts.pinch_in_cmd),
+            ("pinch_out_cmd", &opts.pinch_out_cmd),
+            ("figure_eight_cmd", &opts.figure_eight_cmd),
+            ("level_cmd", &opts.level_cmd),
+            ("analog_cmd", &opts.analog_cmd),
+            ("progress_cmd", &opts.progress_cmd),
+            ("stream_cmd", &opts.stream_cmd),
+        ];
+        Summary {
+            keyboards: &opts.keybd_file,
+            touch_devices: &opts.touchpad_file,
+            keycodes: &opts.keycode_to_monitor.0,
+            trigger_button: opts.trigger_button,
+            arm_fifo: opts.arm_fifo.as_ref(),
+            gesture_type: opts.gesture_type,
+            cw_spin_fraction: opts.cw_spin_fraction.unwrap_or(opts.spin_fraction),
+            ccw_spin_fraction: opts.ccw_spin_fraction.unwrap_or(opts.spin_fraction),
+            gesture_timeout_ms: opts.gesture_timeout_ms,
+            max_jump_distance: opts.max_jump_distance,
+            max_jump_velocity: opts.max_jump_velocity,
+            jump_samples: opts.jump_samples,
+            min_samples_per_spin: opts.min_samples_per_spin,
+            min_quadrants: opts.min_quadrants,
+            min_angular_velocity: opts.min_angular_velocity,
+            smoothing: opts.smoothing,
+            after_buttonpress_attention_time_ms: opts.after_buttonpress_attention_time_ms,
+            after_spin_attention_time_ms: opts.after_spin_attention_time_ms,
+            cmd_dir: opts.cmd_dir.as_ref(),
+            dry_run: opts.dry_run,
+            commands: commands
+                .into_iter()
+                .filter_map(|(name, cmd)| Some((name, cmd.as_deref()?)))
+                .collect(),
+            zones: zones
+                .iter()
+                .map(|z| ZoneSummary {
+                    name: &z.name,
+                    center_x: z.center_x,
+                    center_y: z.center_y,
+                    radius: z.config.radius,
+                    inner_radius: z.config.inner_radius,
+                    cw_spins_required: z.cw_spins_required,
+                    ccw_spins_required: z.ccw_spins_required,
+                    cw_cmd: z.cw_cmd.as_deref(),
+                    ccw_cmd: z.ccw_cmd.as_deref(),
+                    keys: &z.keys,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Log the summary, or print it as a JSON event with `--json`
+pub fn print(opts: &Opts, zones: &[Zone]) {
+    let summary = Summary::new(opts, zones);
+    if opts.json {
+        events::emit(true, events::Event::Config(summary));
+        return;
+    }
+    match toml::to_string(&summary) {
+        Ok(text) => log::info!("Configuration:\n{}", text.trim_end()),
+        Err(e) => log::error!("Failed to serialize configuration: {}", e),
+    }
+}
@@ -0,0 +1,121 @@
+//! Service readiness and watchdog notifications for systemd, available with the `systemd` cargo feature
+
+use crate::Error;
+
+#[cfg(feature = "systemd")]
+use std::{
+    os::unix::net::{SocketAddr, UnixDatagram},
+    time::{Duration, Instant},
+};
+
+#[cfg(all(feature = "systemd", target_os = "android"))]
+use std::os::android::net::SocketAddrExt;
+#[cfg(all(feature = "systemd", target_os = "linux"))]
+use std::os::linux::net::SocketAddrExt;
+
+/// Sends `sd_notify` messages to `$NOTIFY_SOCKET`
+#[cfg(feature = "systemd")]
+pub struct Notifier {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    /// Time between `WATCHDOG=1` pings, half of `WatchdogSec`
+    watchdog: Option<Duration>,
+    last_ping: Instant,
+}
+
+#[cfg(feature = "systemd")]
+impl Notifier {
+    /// Connect to the socket systemd passed in the environment, if any
+    pub fn from_env() -> Result<Option<Notifier>, Error> {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return Ok(None);
+        };
+        let path = path.to_string_lossy();
+        let addr = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(&*path)?,
+        };
+        // Watchdog may be meant for another process, e.g. the shell script that started us
+        let for_us =
+            std::env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse() == Ok(std::process::id()));
+        let watchdog = match std::env::var("WATCHDOG_USEC") {
+            Ok(usec) if for_us => Some(Duration::from_micros(usec.parse()?) / 2),
+            _ => None,
+        };
+        Ok(Some(Notifier {
+            socket: UnixDatagram::unbound()?,
+            addr,
+            watchdog,
+            last_ping: Instant::now(),
+        }))
+    }
+
+    fn notify(&self, message: &str) {
+        if let Err(e) = self.socket.send_to_addr(message.as_bytes(), &self.addr) {
+            log::warn!("Failed to notify systemd: {}", e);
+        }
+    }
+
+    /// Devices are open and the main loop starts
+    pub fn ready(&self) {
+        self.notify("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.notify("STOPPING=1");
+    }
+
+    /// Ping the watchdog if it's time to
+    pub fn poll(&mut self) {
+        let Some(interval) = self.watchdog else {
+            return;
+        };
+        if self.last_ping.elapsed() >= interval {
+            self.notify("WATCHDOG=1");
+            self.last_ping = Instant::now();
+        }
+    }
+
+    /// Shorten `poll` timeout in milliseconds (-1 for none) to wake up for the next watchdog ping
+    pub fn timeout(&self, timeout: i32) -> i32 {
+        let Some(interval) = self.watchdog else {
+            return timeout;
+        };
+        let due = (self.last_ping + interval).saturating_duration_since(Instant::now());
+        let due = due.as_millis() as i32 + 1;
+        match timeout {
+            t if t < 0 => due,
+            t => t.min(due),
+        }
+    }
+}
+
+/// Can't be constructed without systemd support
+#[cfg(not(feature = "systemd"))]
+pub enum Notifier {}
+
+#[cfg(not(feature = "systemd"))]
+impl Notifier {
+    pub fn from_env() -> Result<Option<Notifier>, Error> {
+        if std::env::var_os("NOTIFY_SOCKET").is_some() {
+            log::debug!("Built without systemd support, not notifying it");
+        }
+        Ok(None)
+    }
+
+    pub fn ready(&self) {
+        match *self {}
+    }
+
+    pub fn stopping(&self) {
+        match *self {}
+    }
+
+    pub fn poll(&mut self) {
+        match *self {}
+    }
+
+    pub fn timeout(&self, _timeout: i32) -> i32 {
+        match *self {}
+    }
+}
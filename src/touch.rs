@@ -0,0 +1,473 @@
+use std::collections::BTreeMap;
+
+use andrgesture::Point;
+use euclid::point2;
+use evdev::{AbsoluteAxisType, InputEvent, InputEventKind, Key, RelativeAxisType, Synchronization};
+
+struct Contact {
+    /// Position is kept after lifting, as the kernel only reports changed values for the slot
+    active: bool,
+    x: Option<i32>,
+    y: Option<i32>,
+    /// Not known on devices that don't report pressure
+    pressure: Option<i32>,
+    /// Not known on devices that don't report contact size
+    touch_major: Option<i32>,
+    /// Order of touching down, to find the first finger
+    seq: u64,
+}
+
+/// Value of `--track`: which point of several fingers drives the gesture
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrackMode {
+    /// The first touched finger
+    #[default]
+    First,
+    /// Average position of all fingers
+    Centroid,
+}
+
+impl std::str::FromStr for TrackMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(TrackMode::First),
+            "centroid" => Ok(TrackMode::Centroid),
+            _ => Err(format!("Expected `first` or `centroid`, got `{}`", s)),
+        }
+    }
+}
+
+/// Tracks individual fingers using multitouch slots and tracking IDs
+#[derive(Default)]
+pub struct MtTracker {
+    slot: i32,
+    contacts: BTreeMap<i32, Contact>,
+    /// Slot of the finger that drives the gesture. In centroid mode, any slot while the gesture goes on.
+    designated: Option<i32>,
+    seq: u64,
+    has_tracking_ids: bool,
+    /// Seen ABS_MT_POSITION_X/Y, so ABS_X/ABS_Y are only the kernel's pointer emulation
+    has_mt_positions: bool,
+    /// Lighter contacts are ignored, 0 to disable
+    min_pressure: i32,
+    /// Larger contacts (palms) are ignored, 0 to disable
+    max_touch_major: i32,
+    mode: TrackMode,
+}
+
+impl Contact {
+    fn usable(&self, min_pressure: i32, max_touch_major: i32) -> bool {
+        self.active
+            && self.pressure.is_none_or(|p| p >= min_pressure)
+            && self
+                .touch_major
+                .is_none_or(|m| max_touch_major == 0 || m <= max_touch_major)
+    }
+}
+
+impl MtTracker {
+    pub fn new(min_pressure: i32, max_touch_major: i32, mode: TrackMode) -> MtTracker {
+        MtTracker {
+            min_pressure,
+            max_touch_major,
+            mode,
+            ..Default::default()
+        }
+    }
+
+    /// Contact in the current slot stopped being usable. Returns `true` if the gesture should end.
+    fn contact_ended(&mut self) -> bool {
+        let ended = match self.mode {
+            TrackMode::First => self.designated == Some(self.slot),
+            TrackMode::Centroid => !self
+                .contacts
+                .values()
+                .any(|c| c.usable(self.min_pressure, self.max_touch_major)),
+        };
+        ended && self.designated.take().is_some()
+    }
+
+    /// Returns `true` if the designated finger (all fingers in centroid mode) got lifted, so the gesture should end
+    pub fn process(&mut self, ev: &InputEvent) -> bool {
+        let axis = match ev.kind() {
+            InputEventKind::AbsAxis(a) => a,
+            InputEventKind::Key(Key::BTN_TOUCH) => return self.process_btn_touch(ev.value() != 0),
+            _ => return false,
+        };
+        match axis {
+            AbsoluteAxisType::ABS_MT_SLOT => self.slot = ev.value(),
+            AbsoluteAxisType::ABS_MT_TRACKING_ID => {
+                self.has_tracking_ids = true;
+                if ev.value() == -1 {
+                    self.contact_mut().active = false;
+                    return self.contact_ended();
+                } else {
+                    self.seq += 1;
+                    let seq = self.seq;
+                    let c = self.contact_mut();
+                    c.active = true;
+                    c.seq = seq;
+                }
+            }
+            // Devices without tracking IDs get their contacts created implicitly
+            AbsoluteAxisType::ABS_MT_POSITION_X => {
+                self.has_mt_positions = true;
+                self.contact_mut().x = Some(ev.value());
+            }
+            AbsoluteAxisType::ABS_MT_POSITION_Y => {
+                self.has_mt_positions = true;
+                self.contact_mut().y = Some(ev.value());
+            }
+            // Single-touch panels, as one contact in slot 0. Multitouch drivers report MT positions
+            // earlier in the same frame, so the emulated pointer is never taken for a contact.
+            AbsoluteAxisType::ABS_X if !self.has_mt_positions => {
+                self.contact_mut().x = Some(ev.value());
+            }
+            AbsoluteAxisType::ABS_Y if !self.has_mt_positions => {
+                self.contact_mut().y = Some(ev.value());
+            }
+            AbsoluteAxisType::ABS_MT_PRESSURE => {
+                self.contact_mut().pressure = Some(ev.value());
+                // Becoming too light is the same as lifting
+                if ev.value() < self.min_pressure {
+                    return self.contact_ended();
+                }
+            }
+            AbsoluteAxisType::ABS_MT_TOUCH_MAJOR => {
+                self.contact_mut().touch_major = Some(ev.value());
+                // A fingertip turning into a palm is the same as lifting
+                if self.max_touch_major > 0 && ev.value() > self.max_touch_major {
+                    return self.contact_ended();
+                }
+            }
+            _ => (),
+        }
+        false
+    }
+
+    /// All fingers are up when `BTN_TOUCH` is released
+    fn process_btn_touch(&mut self, pressed: bool) -> bool {
+        if pressed {
+            if !self.has_tracking_ids {
+                self.seq += 1;
+                let seq = self.seq;
+                let c = self.contact_mut();
+                c.active = true;
+                c.seq = seq;
+            }
+            return false;
+        }
+        for c in self.contacts.values_mut() {
+            c.active = false;
+        }
+        self.designated.take().is_some()
+    }
+
+    fn contact_mut(&mut self) -> &mut Contact {
+        self.contacts.entry(self.slot).or_insert(Contact {
+            active: true,
+            x: None,
+            y: None,
+            pressure: None,
+            touch_major: None,
+            seq: 0,
+        })
+    }
+
+    /// Position of the designated finger, choosing the first touched finger if there is none yet.
+    /// Average position of all fingers in centroid mode.
+    pub fn point(&mut self) -> Option<Point> {
+        if self.mode == TrackMode::Centroid {
+            return self.centroid();
+        }
+        if self.designated.is_none() {
+            self.designated = self
+                .contacts
+                .iter()
+                .filter(|(_, c)| {
+                    c.usable(self.min_pressure, self.max_touch_major)
+                        && c.x.is_some()
+                        && c.y.is_some()
+                })
+                .min_by_key(|(_, c)| c.seq)
+                .map(|(&slot, _)| slot);
+        }
+        let c = self.contacts.get(&self.designated?)?;
+        Some(point2(c.x?, c.y?).to_f32())
+    }
+
+    /// Average position of usable fingers
+    fn centroid(&mut self) -> Option<Point> {
+        let mut sum = euclid::vec2(0.0, 0.0);
+        let mut n = 0;
+        for (&slot, c) in &self.contacts {
+            if let (true, Some(x), Some(y)) =
+                (c.usable(self.min_pressure, self.max_touch_major), c.x, c.y)
+            {
+                sum += euclid::vec2(x, y).to_f32();
+                n += 1;
+                self.designated.get_or_insert(slot);
+            }
+        }
+        (n > 0).then(|| (sum / n as f32).to_point())
+    }
+
+    /// Positions of the two earliest touched active fingers
+    pub fn pair(&self) -> Option<(Point, Point)> {
+        let mut active: Vec<&Contact> = self
+            .contacts
+            .values()
+            .filter(|c| c.usable(self.min_pressure, self.max_touch_major))
+            .collect();
+        active.sort_by_key(|c| c.seq);
+        match active[..] {
+            [a, b, ..] => Some((point2(a.x?, a.y?).to_f32(), point2(b.x?, b.y?).to_f32())),
+            _ => None,
+        }
+    }
+}
+
+/// Accumulates relative motion of a touchpad or mouse into a virtual cursor
+pub struct RelTracker {
+    start: Point,
+    cursor: Point,
+    moved: bool,
+}
+
+impl RelTracker {
+    pub fn new(start: Point) -> RelTracker {
+        RelTracker {
+            start,
+            cursor: start,
+            moved: false,
+        }
+    }
+
+    /// Returns `true` if the contact ended, so the gesture should end
+    pub fn process(&mut self, ev: &InputEvent) -> bool {
+        match ev.kind() {
+            InputEventKind::RelAxis(RelativeAxisType::REL_X) => {
+                self.cursor.x += ev.value() as f32;
+                self.moved = true;
+            }
+            InputEventKind::RelAxis(RelativeAxisType::REL_Y) => {
+                self.cursor.y += ev.value() as f32;
+                self.moved = true;
+            }
+            InputEventKind::Key(Key::BTN_TOUCH) if ev.value() == 0 => {
+                self.cursor = self.start;
+                return std::mem::take(&mut self.moved);
+            }
+            _ => (),
+        }
+        false
+    }
+
+    pub fn point(&mut self) -> Option<Point> {
+        self.moved.then_some(self.cursor)
+    }
+}
+
+enum Kind {
+    Absolute(MtTracker),
+    Relative(RelTracker),
+}
+
+/// Contact tracking that is consistent only at `SYN_REPORT` frame boundaries
+pub struct Tracker {
+    kind: Kind,
+    /// The tracked contact ended during the current frame
+    lifted: bool,
+}
+
+impl Tracker {
+    pub fn absolute(t: MtTracker) -> Tracker {
+        Tracker {
+            kind: Kind::Absolute(t),
+            lifted: false,
+        }
+    }
+
+    pub fn relative(t: RelTracker) -> Tracker {
+        Tracker {
+            kind: Kind::Relative(t),
+            lifted: false,
+        }
+    }
+
+    /// Returns whether the tracked contact ended (so the gesture should end) once `ev` completes a frame.
+    /// Positions shouldn't be read in between.
+    pub fn process(&mut self, ev: &InputEvent) -> Option<bool> {
+        self.lifted |= match &mut self.kind {
+            Kind::Absolute(t) => t.process(ev),
+            Kind::Relative(t) => t.process(ev),
+        };
+        match ev.kind() {
+            InputEventKind::Synchronization(Synchronization::SYN_REPORT) => {
+                Some(std::mem::take(&mut self.lifted))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn point(&mut self) -> Option<Point> {
+        match &mut self.kind {
+            Kind::Absolute(t) => t.point(),
+            Kind::Relative(t) => t.point(),
+        }
+    }
+
+    /// Two finger positions, only available for multitouch devices
+    pub fn pair(&self) -> Option<(Point, Point)> {
+        match &self.kind {
+            Kind::Absolute(t) => t.pair(),
+            Kind::Relative(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use evdev::EventType;
+
+    use super::*;
+
+    fn abs(axis: AbsoluteAxisType, value: i32) -> InputEvent {
+        InputEvent::new(EventType::ABSOLUTE, axis.0, value)
+    }
+
+    /// Events putting a finger with `id` down in `slot` at `x`, `y`
+    fn touch(slot: i32, id: i32, x: i32, y: i32) -> [InputEvent; 4] {
+        [
+            abs(AbsoluteAxisType::ABS_MT_SLOT, slot),
+            abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, id),
+            abs(AbsoluteAxisType::ABS_MT_POSITION_X, x),
+            abs(AbsoluteAxisType::ABS_MT_POSITION_Y, y),
+        ]
+    }
+
+    fn lift(slot: i32) -> [InputEvent; 2] {
+        [
+            abs(AbsoluteAxisType::ABS_MT_SLOT, slot),
+            abs(AbsoluteAxisType::ABS_MT_TRACKING_ID, -1),
+        ]
+    }
+
+    fn feed(t: &mut MtTracker, events: &[InputEvent]) -> bool {
+        events
+            .iter()
+            .fold(false, |lifted, ev| t.process(ev) | lifted)
+    }
+
+    #[test]
+    fn first_finger_drives_the_gesture() {
+        let mut t = MtTracker::default();
+        feed(&mut t, &touch(0, 10, 100, 200));
+        assert_eq!(t.point(), Some(point2(100.0, 200.0)));
+        feed(&mut t, &touch(1, 11, 500, 600));
+        // Moving the first finger after a slot switch
+        feed(&mut t, &[abs(AbsoluteAxisType::ABS_MT_SLOT, 0)]);
+        feed(&mut t, &[abs(AbsoluteAxisType::ABS_MT_POSITION_X, 150)]);
+        assert_eq!(t.point(), Some(point2(150.0, 200.0)));
+    }
+
+    #[test]
+    fn lifting_only_the_designated_finger_ends_the_gesture() {
+        let mut t = MtTracker::default();
+        feed(&mut t, &touch(0, 10, 100, 200));
+        feed(&mut t, &touch(1, 11, 500, 600));
+        t.point();
+        assert!(!feed(&mut t, &lift(1)));
+        assert_eq!(t.point(), Some(point2(100.0, 200.0)));
+        assert!(feed(&mut t, &lift(0)));
+        assert_eq!(t.point(), None);
+    }
+
+    #[test]
+    fn remaining_finger_takes_over_after_lift() {
+        let mut t = MtTracker::default();
+        feed(&mut t, &touch(0, 10, 100, 200));
+        feed(&mut t, &touch(1, 11, 500, 600));
+        t.point();
+        assert!(feed(&mut t, &lift(0)));
+        assert_eq!(t.point(), Some(point2(500.0, 600.0)));
+    }
+
+    #[test]
+    fn pair_is_the_two_earliest_fingers() {
+        let mut t = MtTracker::default();
+        feed(&mut t, &touch(1, 10, 100, 200));
+        assert_eq!(t.pair(), None);
+        feed(&mut t, &touch(0, 11, 300, 400));
+        feed(&mut t, &touch(2, 12, 500, 600));
+        let first = (point2(100.0, 200.0), point2(300.0, 400.0));
+        assert_eq!(t.pair(), Some(first));
+        feed(&mut t, &lift(1));
+        let rest = (point2(300.0, 400.0), point2(500.0, 600.0));
+        assert_eq!(t.pair(), Some(rest));
+    }
+
+    #[test]
+    fn light_and_large_contacts_are_ignored() {
+        let mut t = MtTracker::new(50, 20, TrackMode::First);
+        feed(&mut t, &touch(0, 10, 100, 200));
+        t.point();
+        assert!(feed(&mut t, &[abs(AbsoluteAxisType::ABS_MT_PRESSURE, 10)]));
+        feed(&mut t, &touch(1, 11, 500, 600));
+        assert_eq!(t.point(), Some(point2(500.0, 600.0)));
+        assert!(feed(
+            &mut t,
+            &[abs(AbsoluteAxisType::ABS_MT_TOUCH_MAJOR, 30)]
+        ));
+        assert_eq!(t.point(), None);
+    }
+
+    #[test]
+    fn lift_is_reported_at_frame_end() {
+        let mut t = Tracker::absolute(MtTracker::default());
+        let syn = InputEvent::new(EventType::SYNCHRONIZATION, 0, 0);
+        for ev in touch(0, 10, 100, 200) {
+            assert_eq!(t.process(&ev), None);
+        }
+        assert_eq!(t.process(&syn), Some(false));
+        t.point();
+        for ev in lift(0) {
+            assert_eq!(t.process(&ev), None);
+        }
+        assert_eq!(t.process(&syn), Some(true));
+        assert_eq!(t.process(&syn), Some(false));
+    }
+
+    #[test]
+    fn centroid_averages_fingers_until_all_are_lifted() {
+        let mut t = MtTracker::new(0, 0, TrackMode::Centroid);
+        feed(&mut t, &touch(0, 10, 100, 200));
+        feed(&mut t, &touch(1, 11, 300, 600));
+        assert_eq!(t.point(), Some(point2(200.0, 400.0)));
+        assert!(!feed(&mut t, &lift(0)));
+        assert_eq!(t.point(), Some(point2(300.0, 600.0)));
+        assert!(feed(&mut t, &lift(1)));
+        assert_eq!(t.point(), None);
+    }
+
+    #[test]
+    fn relative_motion_moves_cursor_until_release() {
+        let rel =
+            |axis: RelativeAxisType, value| InputEvent::new(EventType::RELATIVE, axis.0, value);
+        let release = InputEvent::new(EventType::KEY, Key::BTN_TOUCH.code(), 0);
+        let mut t = RelTracker::new(point2(1000.0, 1000.0));
+        assert_eq!(t.point(), None);
+        assert!(!t.process(&rel(RelativeAxisType::REL_X, 30)));
+        assert!(!t.process(&rel(RelativeAxisType::REL_Y, -20)));
+        assert!(!t.process(&rel(RelativeAxisType::REL_X, 5)));
+        assert_eq!(t.point(), Some(point2(1035.0, 980.0)));
+        assert!(t.process(&release));
+        assert_eq!(t.point(), None);
+        // Released without moving
+        assert!(!t.process(&release));
+        t.process(&rel(RelativeAxisType::REL_Y, 1));
+        assert_eq!(t.point(), Some(point2(1000.0, 1001.0)));
+    }
+}
@@ -0,0 +1,282 @@
+use andrgesture::GestureConfig;
+
+use crate::Error;
+
+/// Value of `--zone`: `name=NAME;x=X;y=Y;r=R[;cw=CMD][;ccw=CMD][;cw_spins=N][;ccw_spins=N][;key=KEYCODES]`
+pub struct ZoneSpec {
+    pub name: String,
+    pub center_x: i32,
+    pub center_y: i32,
+    pub radius: i32,
+    pub cw_cmd: Option<String>,
+    pub ccw_cmd: Option<String>,
+    pub cw_spins: Option<usize>,
+    pub ccw_spins: Option<usize>,
+    /// Arming keys the zone is active for, any if empty
+    pub keys: Vec<u16>,
+}
+
+const KEYS: [&str; 9] = [
+    "name",
+    "x",
+    "y",
+    "r",
+    "cw",
+    "ccw",
+    "cw_spins",
+    "ccw_spins",
+    "key",
+];
+
+impl std::str::FromStr for ZoneSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields: Vec<(&str, String)> = vec![];
+        for part in s.split(';') {
+            match part.split_once('=') {
+                Some((k, v)) if KEYS.contains(&k.trim()) => fields.push((k.trim(), v.to_owned())),
+                // Semicolons inside commands don't start a new field
+                _ => match fields.last_mut() {
+                    Some((k, v)) if *k == "cw" || *k == "ccw" => {
+                        v.push(';');
+                        v.push_str(part);
+                    }
+                    _ => return Err(format!("Invalid zone field `{}`", part).into()),
+                },
+            }
+        }
+        let get = |key: &str| {
+            fields
+                .iter()
+                .rev()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.clone())
+        };
+        let required = |key: &str| get(key).ok_or(format!("Zone is missing `{}`", key));
+        Ok(ZoneSpec {
+            name: required("name")?.trim().to_owned(),
+            center_x: required("x")?.trim().parse()?,
+            center_y: required("y")?.trim().parse()?,
+            radius: required("r")?.trim().parse()?,
+            cw_cmd: get("cw"),
+            ccw_cmd: get("ccw"),
+            cw_spins: get("cw_spins").map(|v| v.trim().parse()).transpose()?,
+            ccw_spins: get("ccw_spins").map(|v| v.trim().parse()).transpose()?,
+            keys: match get("key") {
+                Some(v) => v
+                    .split(',')
+                    .map(|k| k.trim().parse())
+                    .collect::<Result<_, _>>()?,
+                None => vec![],
+            },
+        })
+    }
+}
+
+/// Ring area with its own gesture tracking and commands
+#[derive(Clone)]
+pub struct Zone {
+    pub name: String,
+    pub config: GestureConfig,
+    pub center_x: i32,
+    pub center_y: i32,
+    pub cw_cmd: Option<String>,
+    pub ccw_cmd: Option<String>,
+    pub cw_spins_required: usize,
+    pub ccw_spins_required: usize,
+    /// Arming keys the zone is active for, any if empty
+    pub keys: Vec<u16>,
+}
+
+impl Zone {
+    /// Whether the zone is tracked after arming by `key`
+    pub fn active_for(&self, key: u16) -> bool {
+        self.keys.is_empty() || self.keys.contains(&key)
+    }
+
+    /// Change a parameter at runtime, as requested through the control socket
+    pub fn set(&mut self, param: &str, value: &str) -> Result<(), Error> {
+        let c = &mut self.config;
+        match param {
+            "center_x" => {
+                self.center_x = value.parse()?;
+                c.center.x = self.center_x as f32;
+            }
+            "center_y" => {
+                self.center_y = value.parse()?;
+                c.center.y = self.center_y as f32;
+            }
+            "radius" => {
+                let r = finite(param, value)?;
+                if r <= c.inner_radius {
+                    return Err("radius must be larger than inner_radius".into());
+                }
+                c.radius = r;
+            }
+            "inner_radius" => {
+                let r = finite(param, value)?;
+                if r >= c.radius {
+                    return Err("inner_radius must be smaller than radius".into());
+                }
+                c.inner_radius = r;
+            }
+            "spin_fraction" | "cw_spin_fraction" | "ccw_spin_fraction" => {
+                let f = finite(param, value)?;
+                if f <= 0.0 {
+                    return Err(format!("{} must be positive", param).into());
+                }
+                if param != "ccw_spin_fraction" {
+                    c.cw_spin_fraction = f;
+                }
+                if param != "cw_spin_fraction" {
+                    c.ccw_spin_fraction = f;
+                }
+            }
+            "gesture_timeout_ms" => {
+                c.gesture_timeout = std::time::Duration::from_millis(value.parse()?);
+            }
+            "max_jump_distance" => c.max_jump_distance = finite(param, value)?,
+            "cw_spins_required" => self.cw_spins_required = value.parse()?,
+            "ccw_spins_required" => self.ccw_spins_required = value.parse()?,
+            _ => return Err(format!("unknown parameter {}", param).into()),
+        }
+        Ok(())
+    }
+
+    /// Current values of parameters that can be `set`
+    pub fn describe(&self) -> String {
+        let c = &self.config;
+        format!(
+            "zone={} center_x={} center_y={} radius={} inner_radius={} cw_spin_fraction={} ccw_spin_fraction={} \
+             gesture_timeout_ms={} max_jump_distance={} cw_spins_required={} ccw_spins_required={}",
+            self.name,
+            self.center_x,
+            self.center_y,
+            c.radius,
+            c.inner_radius,
+            c.cw_spin_fraction,
+            c.ccw_spin_fraction,
+            c.gesture_timeout.as_millis(),
+            c.max_jump_distance,
+            self.cw_spins_required,
+            self.ccw_spins_required,
+        )
+    }
+}
+
+/// Parse a float parameter, rejecting NaN and infinities that would pass range checks
+fn finite(param: &str, value: &str) -> Result<f32, Error> {
+    let v: f32 = value.parse()?;
+    if !v.is_finite() {
+        return Err(format!("{} must be finite", param).into());
+    }
+    Ok(v)
+}
+
+/// Zones from `--zone` options, or a single one from the global center, radius and commands.
+/// `base` provides the rest of gesture parameters.
+pub fn build(opts: &crate::Opts, base: &GestureConfig) -> Result<Vec<Zone>, Error> {
+    if opts.zone.is_empty() {
+        if base.inner_radius >= base.radius {
+            return Err("Inner radius must be smaller than radius".into());
+        }
+        return Ok(vec![Zone {
+            name: "default".to_owned(),
+            config: base.clone(),
+            center_x: opts.center_x,
+            center_y: opts.center_y,
+            cw_cmd: Some(opts.cmdline_for_cw_spins.clone()),
+            ccw_cmd: Some(opts.cmdline_for_ccw_spins.clone()),
+            cw_spins_required: opts.cw_spins_required,
+            ccw_spins_required: opts.ccw_spins_required,
+            keys: vec![],
+        }]);
+    }
+    let mut zones = vec![];
+    for z in &opts.zone {
+        let radius = z.radius as f32;
+        let inner_radius = opts.inner_radius.map_or(radius / 8.0, |r| r as f32);
+        if inner_radius >= radius {
+            return Err(
+                format!("Zone {}: inner radius must be smaller than radius", z.name).into(),
+            );
+        }
+        zones.push(Zone {
+            name: z.name.clone(),
+            config: GestureConfig {
+                center: euclid::point2(z.center_x, z.center_y).to_f32(),
+                radius,
+                inner_radius,
+                ..base.clone()
+            },
+            center_x: z.center_x,
+            center_y: z.center_y,
+            cw_cmd: z.cw_cmd.clone(),
+            ccw_cmd: z.ccw_cmd.clone(),
+            cw_spins_required: z.cw_spins.unwrap_or(opts.cw_spins_required),
+            ccw_spins_required: z.ccw_spins.unwrap_or(opts.ccw_spins_required),
+            keys: z.keys.clone(),
+        });
+    }
+    Ok(zones)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> ZoneSpec {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_required_fields() {
+        let z = parse("name=volume; x=100;y=200 ;r=50");
+        assert_eq!(z.name, "volume");
+        assert_eq!((z.center_x, z.center_y, z.radius), (100, 200, 50));
+        assert_eq!(
+            (z.cw_cmd, z.ccw_cmd, z.cw_spins, z.ccw_spins),
+            (None, None, None, None)
+        );
+    }
+
+    #[test]
+    fn parse_optional_fields() {
+        let z = parse("name=a;x=1;y=2;r=3;cw=up;ccw=down;cw_spins=2;ccw_spins=4;key=115, 114");
+        assert_eq!(z.cw_cmd.as_deref(), Some("up"));
+        assert_eq!(z.ccw_cmd.as_deref(), Some("down"));
+        assert_eq!((z.cw_spins, z.ccw_spins), (Some(2), Some(4)));
+        assert_eq!(z.keys, [115, 114]);
+    }
+
+    #[test]
+    fn semicolons_stay_in_commands() {
+        let z = parse("name=a;x=1;y=2;r=3;cw=amixer set Master 5%+; notify-send up;ccw=a=b");
+        assert_eq!(
+            z.cw_cmd.as_deref(),
+            Some("amixer set Master 5%+; notify-send up")
+        );
+        // Only the first `=` separates the key
+        assert_eq!(z.ccw_cmd.as_deref(), Some("a=b"));
+    }
+
+    #[test]
+    fn later_fields_win() {
+        assert_eq!(parse("name=a;x=1;y=2;r=3;x=7").center_x, 7);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in [
+            "x=1;y=2;r=3",
+            "name=a;x=1;y=2",
+            "name=a;x=one;y=2;r=3",
+            "name=a;x=1;y=2;r=3;key=up",
+            "name=a;x=1;y=2;r=3;color=red",
+            "name=a;x=1;y=2;r=3;oops",
+        ] {
+            assert!(s.parse::<ZoneSpec>().is_err(), "{}", s);
+        }
+    }
+}
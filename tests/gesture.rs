@@ -0,0 +1,889 @@
+use std::time::{Duration, Instant};
+
+use andrgesture::{
+    detect_spins, feed_figure_eight, feed_pinch, feed_point, feed_swipe, feed_tap, finish_tap,
+    Angle, CancelReason, Direction, FigureEightConfig, GestureConfig, PinchConfig, PinchEvent,
+    Point, SpinEvent, SwipeConfig, SwipeDirection, TapConfig, Transform,
+};
+use euclid::point2;
+
+fn config() -> GestureConfig {
+    GestureConfig {
+        center: point2(1000.0, 1000.0),
+        radius: 500.0,
+        inner_radius: 62.5,
+        radius_hysteresis: 0.0,
+        max_jump_distance: 200.0,
+        max_jump_velocity: None,
+        jump_samples: 1,
+        gesture_timeout: Duration::from_millis(300),
+        adaptive_timeout: None,
+        cw_spin_fraction: 1.0,
+        ccw_spin_fraction: 1.0,
+        smoothing: 0.0,
+        min_angular_velocity: 0.0,
+        invert_direction: false,
+        min_samples_per_spin: 0,
+        keep_on_reversal: false,
+        min_quadrants: 0,
+        continuous: None,
+        warmup: Duration::ZERO,
+        max_duration: None,
+        angle_origin: Angle::zero(),
+    }
+}
+
+/// Point on a circle around the config center, `turns` being the angle in full turns
+fn on_circle(cfg: &GestureConfig, r: f32, turns: f32) -> Point {
+    let a = turns * 2.0 * std::f32::consts::PI;
+    cfg.center + euclid::vec2(a.cos(), a.sin()) * r
+}
+
+/// Points moving from `from` to `to` turns with `per_turn` steps per turn, 10ms apart
+fn circle(cfg: &GestureConfig, from: f32, to: f32, per_turn: f32) -> Vec<(Point, Instant)> {
+    let start = Instant::now();
+    let steps = ((to - from).abs() * per_turn) as usize;
+    (0..=steps)
+        .map(|i| {
+            let t = from + (to - from) * i as f32 / steps as f32;
+            let time = start + Duration::from_millis(10 * i as u64);
+            (on_circle(cfg, 300.0, t), time)
+        })
+        .collect()
+}
+
+/// Feed points moving from `from` to `to` turns in 32 steps per turn
+fn spin(cfg: &GestureConfig, from: f32, to: f32) -> Vec<SpinEvent> {
+    detect_spins(&circle(cfg, from, to, 32.0), cfg)
+}
+
+#[test]
+fn clockwise_spins() {
+    let cfg = config();
+    assert_eq!(
+        spin(&cfg, 0.0, 3.1),
+        vec![
+            SpinEvent::CwSpin(1),
+            SpinEvent::CwSpin(2),
+            SpinEvent::CwSpin(3)
+        ]
+    );
+}
+
+#[test]
+fn counterclockwise_spins() {
+    let cfg = config();
+    assert_eq!(
+        spin(&cfg, 0.0, -2.1),
+        vec![SpinEvent::CcwSpin(-1), SpinEvent::CcwSpin(-2)]
+    );
+}
+
+#[test]
+fn ignores_points_outside_ring() {
+    let cfg = config();
+    let mut gesture = None;
+    let now = Instant::now();
+    for p in [point2(0.0, 0.0), cfg.center, point2(1000.0, 1600.0)] {
+        assert_eq!(feed_point(&mut gesture, p, now, &cfg), None);
+        assert!(gesture.is_none());
+    }
+}
+
+/// Points turning `turns` at distance `r` from the center in 32 steps per turn, 10ms apart
+fn ring(cfg: &GestureConfig, r: f32, turns: f32) -> Vec<(Point, Instant)> {
+    let start = Instant::now();
+    (0..=(turns * 32.0) as usize)
+        .map(|i| {
+            let time = start + Duration::from_millis(10 * i as u64);
+            (on_circle(cfg, r, i as f32 / 32.0), time)
+        })
+        .collect()
+}
+
+#[test]
+fn inner_radius_rejects_spins_near_center() {
+    let cfg = config();
+    let points = ring(&cfg, 100.0, 2.1);
+    assert_eq!(detect_spins(&points, &cfg).len(), 2);
+    let cfg = GestureConfig {
+        inner_radius: 150.0,
+        ..config()
+    };
+    assert!(detect_spins(&points, &cfg).is_empty());
+}
+
+#[test]
+fn zero_inner_radius_accepts_spins_near_center() {
+    let points = ring(&config(), 40.0, 2.1);
+    assert!(detect_spins(&points, &config()).is_empty());
+    let cfg = GestureConfig {
+        inner_radius: 0.0,
+        ..config()
+    };
+    assert_eq!(
+        detect_spins(&points, &cfg),
+        vec![SpinEvent::CwSpin(1), SpinEvent::CwSpin(2)]
+    );
+}
+
+#[test]
+fn slow_drift_is_ignored_with_min_angular_velocity() {
+    let cfg = config();
+    // A turn per 3.2s, about 2 rad/s
+    let fast = ring(&cfg, 300.0, 2.1);
+    let start = fast[0].1;
+    let slow: Vec<_> = fast
+        .iter()
+        .map(|&(p, t)| (p, start + (t - start) * 10))
+        .collect();
+    assert_eq!(detect_spins(&slow, &cfg).len(), 2);
+    let cfg = GestureConfig {
+        min_angular_velocity: 5.0,
+        ..config()
+    };
+    // Ignored rather than cancelled
+    assert!(detect_spins(&slow, &cfg).is_empty());
+    assert_eq!(detect_spins(&fast, &cfg).len(), 2);
+}
+
+#[test]
+fn radius_hysteresis_keeps_gesture_on_ring_edge() {
+    let cfg = config();
+    // Entering just inside the radius, then riding just outside it
+    let mut points = ring(&cfg, 505.0, 2.1);
+    points[0].0 = on_circle(&cfg, 495.0, 0.0);
+    let events = detect_spins(&points, &cfg);
+    assert!(!events.iter().any(|e| matches!(e, SpinEvent::CwSpin(_))));
+    assert!(events.contains(&SpinEvent::Cancelled(CancelReason::Timeout)));
+    let cfg = GestureConfig {
+        radius_hysteresis: 20.0,
+        ..config()
+    };
+    assert_eq!(
+        detect_spins(&points, &cfg),
+        vec![SpinEvent::CwSpin(1), SpinEvent::CwSpin(2)]
+    );
+}
+
+#[test]
+fn smoothing_filters_jitter_but_not_jumps() {
+    // Finger held still with tangential jitter of about 4 degrees each way
+    let cfg = GestureConfig {
+        cw_spin_fraction: 0.01,
+        ccw_spin_fraction: 0.01,
+        ..config()
+    };
+    let start = Instant::now();
+    let jitter: Vec<_> = (0..20)
+        .map(|i| {
+            let turns = match i {
+                0 => 0.0,
+                _ if i % 2 == 0 => 0.01,
+                _ => -0.01,
+            };
+            (
+                on_circle(&cfg, 300.0, turns),
+                start + Duration::from_millis(10 * i),
+            )
+        })
+        .collect();
+    assert!(!detect_spins(&jitter, &cfg).is_empty());
+    let smooth = GestureConfig {
+        smoothing: 0.9,
+        ..cfg
+    };
+    assert!(detect_spins(&jitter, &smooth).is_empty());
+
+    // Raw positions still count for jump detection
+    let mut gesture = None;
+    feed_point(&mut gesture, on_circle(&smooth, 300.0, 0.0), start, &smooth);
+    let event = feed_point(&mut gesture, on_circle(&smooth, 300.0, 0.5), start, &smooth);
+    assert_eq!(event, Some(SpinEvent::Cancelled(CancelReason::Jump)));
+}
+
+#[test]
+fn jump_cancels_gesture() {
+    let cfg = config();
+    let mut gesture = None;
+    let now = Instant::now();
+    feed_point(&mut gesture, on_circle(&cfg, 300.0, 0.0), now, &cfg);
+    assert!(gesture.is_some());
+    let event = feed_point(&mut gesture, on_circle(&cfg, 300.0, 0.5), now, &cfg);
+    assert_eq!(event, Some(SpinEvent::Cancelled(CancelReason::Jump)));
+    assert!(gesture.is_none());
+}
+
+#[test]
+fn timeout_cancels_gesture() {
+    let cfg = config();
+    let mut gesture = None;
+    let now = Instant::now();
+    feed_point(&mut gesture, on_circle(&cfg, 300.0, 0.0), now, &cfg);
+    let later = now + Duration::from_millis(500);
+    let event = feed_point(&mut gesture, on_circle(&cfg, 300.0, 0.01), later, &cfg);
+    assert_eq!(event, Some(SpinEvent::Cancelled(CancelReason::Timeout)));
+}
+
+/// Whether a gesture following `points` survives a pause before the next point
+fn survives_pause(points: &[(Point, Instant)], pause_ms: u64, cfg: &GestureConfig) -> bool {
+    let mut gesture = None;
+    for &(p, t) in points {
+        feed_point(&mut gesture, p, t, cfg);
+    }
+    let &(p, t) = points.last().unwrap();
+    let later = t + Duration::from_millis(pause_ms);
+    feed_point(&mut gesture, p, later, cfg) != Some(SpinEvent::Cancelled(CancelReason::Timeout))
+}
+
+#[test]
+fn quadrant_coverage() {
+    let sparse = GestureConfig {
+        max_jump_distance: 1000.0,
+        ..config()
+    };
+    // Three samples per turn visit only three quadrants
+    let points = circle(&sparse, 0.0, 2.0, 3.0);
+    let with_quadrants = |n| GestureConfig {
+        min_quadrants: n,
+        ..sparse.clone()
+    };
+    let spins = detect_spins(&points, &sparse);
+    assert!(!spins.is_empty());
+    assert_eq!(detect_spins(&points, &with_quadrants(3)), spins);
+    assert_eq!(detect_spins(&points, &with_quadrants(4)), vec![]);
+}
+
+#[test]
+fn adaptive_timeout_follows_speed() {
+    let fixed = config();
+    let cfg = GestureConfig {
+        adaptive_timeout: Some((Duration::from_millis(100), Duration::from_millis(2000))),
+        ..config()
+    };
+    // About 0.3 and 3 turns per second
+    let slow = circle(&cfg, 0.0, 0.25, 320.0);
+    let fast = circle(&cfg, 0.0, 0.25, 32.0);
+    assert!(!survives_pause(&slow, 500, &fixed));
+    assert!(survives_pause(&slow, 500, &cfg));
+    assert!(survives_pause(&fast, 200, &fixed));
+    assert!(!survives_pause(&fast, 200, &cfg));
+}
+
+#[test]
+fn ring_angle_from_origin() {
+    use andrgesture::ring_angle;
+    let up_right = euclid::vec2(1.0, -1.0);
+    let a = ring_angle(up_right, Angle::zero());
+    // `angle_from_x_axis` is an approximation
+    assert!((a.to_degrees() - 315.0).abs() < 0.1);
+    let a = ring_angle(up_right, Angle::degrees(270.0));
+    assert!((a.to_degrees() - 45.0).abs() < 0.1);
+    let a = ring_angle(euclid::vec2(0.0, -1.0), Angle::degrees(-90.0));
+    assert!(a.to_degrees() < 0.1 || a.to_degrees() > 359.9);
+}
+
+#[test]
+fn angle_delta_across_wrap() {
+    use andrgesture::angle_delta;
+    let d = angle_delta(Angle::degrees(179.0), Angle::degrees(-179.0));
+    assert!((d.to_degrees() - 2.0).abs() < 1e-3);
+    let d = angle_delta(Angle::degrees(-179.0), Angle::degrees(179.0));
+    assert!((d.to_degrees() + 2.0).abs() < 1e-3);
+    let d = angle_delta(Angle::degrees(10.0), Angle::degrees(370.0));
+    assert!(d.abs() < 1e-3);
+    let d = angle_delta(Angle::degrees(0.0), Angle::degrees(180.0));
+    assert!((d - andrgesture::MAX_ANGLE_STEP).abs() < 1e-6);
+}
+
+#[test]
+fn spinner_is_monotonic_across_wrap() {
+    let cfg = config();
+    let mut gesture = None;
+    let mut now = Instant::now();
+    let mut prev_spinner = 0.0;
+    // From 3/8 to 5/8 of a turn, passing the -x axis where angles wrap from π to -π
+    for i in 0..=64 {
+        let t = 0.375 + 0.25 * i as f32 / 64.0;
+        feed_point(&mut gesture, on_circle(&cfg, 300.0, t), now, &cfg);
+        let spinner = gesture.as_ref().unwrap().spinner;
+        assert!(spinner >= prev_spinner);
+        prev_spinner = spinner;
+        now += Duration::from_millis(10);
+    }
+    assert!((prev_spinner - 0.25).abs() < 1e-3);
+}
+
+#[test]
+fn quarter_turn_fraction() {
+    let cfg = GestureConfig {
+        cw_spin_fraction: 0.25,
+        ccw_spin_fraction: 0.25,
+        ..config()
+    };
+    assert_eq!(
+        spin(&cfg, 0.0, 1.1),
+        (1..=4).map(SpinEvent::CwSpin).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        spin(&cfg, 0.0, -0.6),
+        vec![SpinEvent::CcwSpin(-1), SpinEvent::CcwSpin(-2)]
+    );
+}
+
+#[test]
+fn per_direction_fractions() {
+    let cfg = GestureConfig {
+        cw_spin_fraction: 0.25,
+        ccw_spin_fraction: 1.0,
+        ..config()
+    };
+    assert_eq!(
+        spin(&cfg, 0.0, 1.1),
+        (1..=4).map(SpinEvent::CwSpin).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        spin(&cfg, 0.0, -2.1),
+        vec![SpinEvent::CcwSpin(-1), SpinEvent::CcwSpin(-2)]
+    );
+}
+
+#[test]
+fn per_direction_fractions_count_down_by_side() {
+    let cfg = GestureConfig {
+        cw_spin_fraction: 0.5,
+        ccw_spin_fraction: 1.0,
+        keep_on_reversal: true,
+        ..config()
+    };
+    let mut gesture = None;
+    let mut now = Instant::now();
+    let mut events = vec![];
+    // Up to 1.1 turns clockwise, then back to -1.3
+    for t in (0..=36).chain((-42..36).rev()) {
+        let p = on_circle(&cfg, 300.0, t as f32 / 32.0);
+        events.extend(feed_point(&mut gesture, p, now, &cfg));
+        now += Duration::from_millis(10);
+    }
+    use SpinEvent::*;
+    // Half turns down to zero, then full turns counterclockwise
+    assert_eq!(
+        events,
+        vec![CwSpin(1), CwSpin(2), CcwSpin(1), CcwSpin(0), CcwSpin(-1)]
+    );
+}
+
+#[test]
+fn fraction_reversal_cancels() {
+    let cfg = GestureConfig {
+        cw_spin_fraction: 0.5,
+        ccw_spin_fraction: 0.5,
+        ..config()
+    };
+    let mut gesture = None;
+    let mut now = Instant::now();
+    let mut events = vec![];
+    for t in (0..=20).chain((-8..20).rev()) {
+        let p = on_circle(&cfg, 300.0, t as f32 / 32.0);
+        events.extend(feed_point(&mut gesture, p, now, &cfg));
+        now += Duration::from_millis(10);
+    }
+    assert_eq!(
+        events,
+        vec![
+            SpinEvent::CwSpin(1),
+            SpinEvent::Cancelled(CancelReason::OppositeDirection)
+        ]
+    );
+}
+
+#[test]
+fn reversal_counts_down() {
+    let cfg = GestureConfig {
+        keep_on_reversal: true,
+        ..config()
+    };
+    let mut gesture = None;
+    let mut now = Instant::now();
+    let mut events = vec![];
+    for t in (0..=68).chain((-40..68).rev()) {
+        let p = on_circle(&cfg, 300.0, t as f32 / 32.0);
+        events.extend(feed_point(&mut gesture, p, now, &cfg));
+        now += Duration::from_millis(10);
+    }
+    use SpinEvent::*;
+    assert_eq!(
+        events,
+        vec![CwSpin(1), CwSpin(2), CcwSpin(1), CcwSpin(0), CcwSpin(-1)]
+    );
+    let reversals: Vec<bool> = events.iter().map(|e| e.is_reversal()).collect();
+    assert_eq!(reversals, vec![false, false, true, true, false]);
+}
+
+#[test]
+fn single_spike_is_dropped() {
+    let cfg = GestureConfig {
+        jump_samples: 2,
+        ..config()
+    };
+    let mut gesture = None;
+    let mut now = Instant::now();
+    let mut events = vec![];
+    for i in 0..=(32 * 3 + 4) {
+        if i == 40 {
+            let spike = point2(0.0, 0.0);
+            assert_eq!(feed_point(&mut gesture, spike, now, &cfg), None);
+        }
+        let p = on_circle(&cfg, 300.0, i as f32 / 32.0);
+        events.extend(feed_point(&mut gesture, p, now, &cfg));
+        now += Duration::from_millis(10);
+    }
+    assert_eq!(
+        events,
+        vec![
+            SpinEvent::CwSpin(1),
+            SpinEvent::CwSpin(2),
+            SpinEvent::CwSpin(3)
+        ]
+    );
+}
+
+#[test]
+fn consecutive_jumps_cancel() {
+    let cfg = GestureConfig {
+        jump_samples: 2,
+        ..config()
+    };
+    let mut gesture = None;
+    let now = Instant::now();
+    feed_point(&mut gesture, on_circle(&cfg, 300.0, 0.0), now, &cfg);
+    let far = on_circle(&cfg, 300.0, 0.5);
+    assert_eq!(feed_point(&mut gesture, far, now, &cfg), None);
+    assert_eq!(
+        feed_point(&mut gesture, far, now, &cfg),
+        Some(SpinEvent::Cancelled(CancelReason::Jump))
+    );
+}
+
+#[test]
+fn jump_velocity_scales_with_interval() {
+    let cfg = GestureConfig {
+        max_jump_velocity: Some(10_000.0),
+        ..config()
+    };
+    // Quarter turn steps are about 420 apart, fine at 50ms...
+    let mut gesture = None;
+    let mut now = Instant::now();
+    let mut events = vec![];
+    for i in 0..=8 {
+        events.extend(feed_point(
+            &mut gesture,
+            on_circle(&cfg, 300.0, i as f32 / 4.0),
+            now,
+            &cfg,
+        ));
+        now += Duration::from_millis(50);
+    }
+    assert_eq!(events, vec![SpinEvent::CwSpin(1), SpinEvent::CwSpin(2)]);
+    // ...but not at 5ms
+    let mut gesture = None;
+    let now = Instant::now();
+    feed_point(&mut gesture, on_circle(&cfg, 300.0, 0.0), now, &cfg);
+    let quarter = on_circle(&cfg, 300.0, 0.25);
+    let later = now + Duration::from_millis(5);
+    assert_eq!(
+        feed_point(&mut gesture, quarter, later, &cfg),
+        Some(SpinEvent::Cancelled(CancelReason::Jump))
+    );
+}
+
+/// Spinner after a clockwise quarter turn (as seen on the screen) transformed by `t`
+fn transformed_quarter_spin(t: Transform) -> f32 {
+    let cfg = config();
+    let mut gesture = None;
+    let mut now = Instant::now();
+    for i in 0..=8 {
+        let p = on_circle(&cfg, 300.0, i as f32 / 32.0);
+        let p = t.apply(p);
+        feed_point(&mut gesture, p, now, &cfg);
+        now += Duration::from_millis(10);
+    }
+    gesture.unwrap().spinner
+}
+
+#[test]
+fn rotation_keeps_clockwise_direction() {
+    // Square ranges centered around config center, so that the ring stays in place
+    for rotation in [0, 90, 180, 270] {
+        let t = Transform {
+            rotation,
+            flip_x: false,
+            flip_y: false,
+            x_range: (0.0, 2000.0),
+            y_range: (0.0, 2000.0),
+        };
+        let spinner = transformed_quarter_spin(t);
+        assert!((spinner - 0.25).abs() < 1e-3, "{} {}", rotation, spinner);
+    }
+}
+
+#[test]
+fn flip_inverts_direction() {
+    for (flip_x, flip_y) in [(true, false), (false, true)] {
+        let t = Transform {
+            rotation: 90,
+            flip_x,
+            flip_y,
+            x_range: (0.0, 2000.0),
+            y_range: (0.0, 2000.0),
+        };
+        let spinner = transformed_quarter_spin(t);
+        assert!((spinner + 0.25).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn rotation_maps_corners() {
+    let t = Transform {
+        rotation: 90,
+        flip_x: false,
+        flip_y: false,
+        x_range: (0.0, 1000.0),
+        y_range: (0.0, 2000.0),
+    };
+    // Top-left corner of portrait panel becomes top-right of landscape
+    assert_eq!(t.apply(point2(0.0, 0.0)), point2(2000.0, 0.0));
+    assert_eq!(t.apply(point2(1000.0, 2000.0)), point2(0.0, 1000.0));
+}
+
+#[test]
+fn inverted_direction() {
+    let cfg = GestureConfig {
+        invert_direction: true,
+        ..config()
+    };
+    assert_eq!(
+        spin(&cfg, 0.0, 2.1),
+        vec![SpinEvent::CcwSpin(-1), SpinEvent::CcwSpin(-2)]
+    );
+    assert_eq!(spin(&cfg, 0.0, -1.1), vec![SpinEvent::CwSpin(1)]);
+}
+
+/// Feed points along a straight line from `from` by `step` per sample, `interval` apart
+fn swipe(
+    from: Point,
+    step: euclid::default::Vector2D<f32>,
+    n: usize,
+    interval: u64,
+) -> Vec<SwipeDirection> {
+    let cfg = config();
+    let swipe_cfg = SwipeConfig {
+        distance: 300.0,
+        timeout: Duration::from_millis(500),
+    };
+    let mut state = None;
+    let mut now = Instant::now();
+    let mut events = vec![];
+    for i in 0..=n {
+        let p = from + step * i as f32;
+        events.extend(feed_swipe(&mut state, p, now, &cfg, &swipe_cfg));
+        now += Duration::from_millis(interval);
+    }
+    events
+}
+
+#[test]
+fn swipe_directions() {
+    let c = config().center;
+    assert_eq!(
+        swipe(c, euclid::vec2(40.0, 5.0), 20, 10),
+        vec![SwipeDirection::Right]
+    );
+    assert_eq!(
+        swipe(c, euclid::vec2(-40.0, 5.0), 20, 10),
+        vec![SwipeDirection::Left]
+    );
+    // y axis points down
+    assert_eq!(
+        swipe(c, euclid::vec2(5.0, -40.0), 20, 10),
+        vec![SwipeDirection::Up]
+    );
+    assert_eq!(
+        swipe(c, euclid::vec2(5.0, 40.0), 20, 10),
+        vec![SwipeDirection::Down]
+    );
+}
+
+#[test]
+fn slow_or_outside_swipe_is_ignored() {
+    let c = config().center;
+    assert!(swipe(c, euclid::vec2(10.0, 0.0), 100, 50).is_empty());
+    let outside = c + euclid::vec2(600.0, 0.0);
+    assert!(swipe(outside, euclid::vec2(40.0, 0.0), 20, 10).is_empty());
+}
+
+#[test]
+fn pinch_out_then_lift_cancels() {
+    let cfg = PinchConfig {
+        ratio: 1.5,
+        timeout: Duration::from_millis(1000),
+    };
+    let c = config().center;
+    let mut state = None;
+    let mut now = Instant::now();
+    let mut events = vec![];
+    for d in [100.0, 120.0, 140.0, 160.0, 180.0] {
+        let pair = (c - euclid::vec2(d, 0.0), c + euclid::vec2(d, 0.0));
+        events.extend(feed_pinch(&mut state, Some(pair), now, &cfg));
+        now += Duration::from_millis(50);
+    }
+    assert_eq!(events, vec![PinchEvent::Out]);
+    assert_eq!(
+        feed_pinch(&mut state, None, now, &cfg),
+        Some(PinchEvent::Cancelled)
+    );
+    assert_eq!(feed_pinch(&mut state, None, now, &cfg), None);
+
+    let pair = (c, c + euclid::vec2(300.0, 0.0));
+    feed_pinch(&mut state, Some(pair), now, &cfg);
+    let pair = (c, c + euclid::vec2(150.0, 0.0));
+    assert_eq!(
+        feed_pinch(&mut state, Some(pair), now, &cfg),
+        Some(PinchEvent::In)
+    );
+}
+
+#[test]
+fn tap_detection() {
+    let cfg = TapConfig {
+        max_duration: Duration::from_millis(250),
+        max_movement: 40.0,
+    };
+    let p = point2(100.0, 100.0);
+    let now = Instant::now();
+    let mut tap = None;
+    feed_tap(&mut tap, p, now, &cfg);
+    feed_tap(&mut tap, p + euclid::vec2(10.0, 10.0), now, &cfg);
+    assert_eq!(
+        finish_tap(&mut tap, now + Duration::from_millis(100), &cfg),
+        Some(p)
+    );
+
+    feed_tap(&mut tap, p, now, &cfg);
+    assert_eq!(
+        finish_tap(&mut tap, now + Duration::from_millis(300), &cfg),
+        None
+    );
+
+    feed_tap(&mut tap, p, now, &cfg);
+    feed_tap(&mut tap, p + euclid::vec2(50.0, 0.0), now, &cfg);
+    feed_tap(&mut tap, p, now, &cfg);
+    assert_eq!(finish_tap(&mut tap, now, &cfg), None);
+}
+
+#[test]
+fn circle_table() {
+    let cfg = GestureConfig {
+        max_jump_distance: 1000.0,
+        ..config()
+    };
+    // (turns, points per turn, spin count of the last event)
+    let table = [
+        (1.05, 8.0, Some(1)),
+        (3.05, 16.0, Some(3)),
+        (2.5, 64.0, Some(2)),
+        (0.9, 32.0, None),
+        (-1.05, 8.0, Some(-1)),
+        (-4.05, 100.0, Some(-4)),
+    ];
+    for (turns, per_turn, expected) in table {
+        let events = detect_spins(&circle(&cfg, 0.0, turns, per_turn), &cfg);
+        let last = events.last().and_then(|e| e.spin()).map(|(_, n)| n);
+        assert_eq!(last, expected, "{} turns, {} per turn", turns, per_turn);
+        let count = expected.map_or(0, |n: i32| n.unsigned_abs() as usize);
+        assert_eq!(events.len(), count);
+    }
+}
+
+#[test]
+fn too_few_samples_defer_spin() {
+    let fast = GestureConfig {
+        max_jump_distance: 1000.0,
+        ..config()
+    };
+    let points = circle(&fast, 0.0, 3.1, 4.0);
+    assert_eq!(detect_spins(&points, &fast).len(), 3);
+    let cfg = GestureConfig {
+        min_samples_per_spin: 8,
+        ..fast
+    };
+    assert_eq!(detect_spins(&points, &cfg), vec![SpinEvent::CwSpin(1)]);
+}
+
+/// Feed the points into a spin gesture and figure-eight detection, returning figure-eights found
+fn figure_eights(cfg: &GestureConfig, points: &[(Point, Instant)]) -> Vec<Direction> {
+    let fig_cfg = FigureEightConfig {
+        fraction: 0.5,
+        timeout: Duration::from_millis(1500),
+    };
+    let mut gesture = None;
+    let mut fig = None;
+    let mut found = vec![];
+    for &(p, t) in points {
+        feed_point(&mut gesture, p, t, cfg);
+        if let Some(g) = &gesture {
+            found.extend(feed_figure_eight(&mut fig, g.spinner, t, &fig_cfg));
+        }
+    }
+    found
+}
+
+#[test]
+fn figure_eight() {
+    let cfg = config();
+    let mut points = circle(&cfg, 0.0, 0.7, 32.0);
+    let end = points.last().unwrap().1;
+    let back = circle(&cfg, 0.7, 0.1, 32.0);
+    points.extend(back.iter().map(|&(p, t)| (p, end + (t - back[0].1))));
+    assert_eq!(figure_eights(&cfg, &points), vec![Direction::Cw]);
+
+    // Turning back too little or not at all
+    assert!(figure_eights(&cfg, &points[..points.len() - 8]).is_empty());
+    assert!(figure_eights(&cfg, &circle(&cfg, 0.0, -0.9, 32.0)).is_empty());
+}
+
+#[test]
+fn continuous_resets_count_after_pause() {
+    let cfg = GestureConfig {
+        continuous: Some(Duration::from_millis(200)),
+        gesture_timeout: Duration::from_secs(10),
+        ..config()
+    };
+    // 40ms per sample, 64 samples per turn makes 2.56s per spin
+    let start = Instant::now();
+    let slow: Vec<_> = circle(&cfg, 0.0, 1.1, 64.0)
+        .into_iter()
+        .enumerate()
+        .map(|(i, (p, _))| (p, start + Duration::from_millis(40 * i as u64)))
+        .collect();
+    let mut points = circle(&cfg, 0.0, 1.1, 32.0);
+    let end = points.last().unwrap().1;
+    points.extend(slow.iter().map(|&(p, t)| (p, end + (t - start))));
+    assert_eq!(
+        detect_spins(&points, &cfg),
+        vec![SpinEvent::CwSpin(1), SpinEvent::CwSpin(1)]
+    );
+    let cfg = GestureConfig {
+        continuous: None,
+        ..cfg
+    };
+    assert_eq!(
+        detect_spins(&points, &cfg),
+        vec![SpinEvent::CwSpin(1), SpinEvent::CwSpin(2)]
+    );
+}
+
+#[test]
+fn warmup_ignores_start_of_gesture() {
+    // 32 samples per turn 10ms apart, so 80ms is a quarter turn
+    let cfg = GestureConfig {
+        warmup: Duration::from_millis(80),
+        ..config()
+    };
+    assert_eq!(spin(&cfg, 0.0, 1.1), vec![]);
+    assert_eq!(spin(&cfg, 0.0, 1.3), vec![SpinEvent::CwSpin(1)]);
+}
+
+#[test]
+fn max_duration_ends_ongoing_gesture() {
+    // 320ms per turn
+    let cfg = GestureConfig {
+        max_duration: Some(Duration::from_millis(700)),
+        ..config()
+    };
+    use SpinEvent::*;
+    assert_eq!(
+        spin(&cfg, 0.0, 3.0),
+        vec![CwSpin(1), CwSpin(2), Cancelled(CancelReason::MaxDuration)]
+    );
+}
+
+/// Exactly `turns` full turns (negative for counterclockwise) of `per_turn` evenly spaced points, ending where they started
+fn perfect_circle(cfg: &GestureConfig, turns: i32, per_turn: u32) -> Vec<SpinEvent> {
+    let steps = turns.unsigned_abs() * per_turn;
+    let start = Instant::now();
+    let points: Vec<_> = (0..=steps)
+        .map(|i| {
+            let t = turns.signum() as f32 * i as f32 / per_turn as f32;
+            (
+                on_circle(cfg, 300.0, t),
+                start + Duration::from_millis(10 * i as u64),
+            )
+        })
+        .collect();
+    detect_spins(&points, cfg)
+}
+
+#[test]
+fn perfect_clockwise_circles_count_exactly() {
+    let cfg = config();
+    for per_turn in [16, 32, 64, 100] {
+        for turns in 1..=5 {
+            assert_eq!(
+                perfect_circle(&cfg, turns, per_turn),
+                (1..=turns).map(SpinEvent::CwSpin).collect::<Vec<_>>(),
+                "{} turns of {} points",
+                turns,
+                per_turn
+            );
+        }
+    }
+}
+
+#[test]
+fn perfect_counterclockwise_circles_count_exactly() {
+    let cfg = config();
+    for per_turn in [16, 32, 64, 100] {
+        for turns in 1..=5 {
+            assert_eq!(
+                perfect_circle(&cfg, -turns, per_turn),
+                (1..=turns)
+                    .map(|n| SpinEvent::CcwSpin(-n))
+                    .collect::<Vec<_>>(),
+                "{} turns of {} points",
+                turns,
+                per_turn
+            );
+        }
+    }
+}
+
+#[test]
+fn clockwise_then_counterclockwise_turn() {
+    // One turn there and a bit more than one turn back, 32 steps per turn
+    let points = |cfg: &GestureConfig| {
+        let start = Instant::now();
+        (0..=32)
+            .chain((-4..32).rev())
+            .enumerate()
+            .map(|(i, t)| {
+                let time = start + Duration::from_millis(10 * i as u64);
+                (on_circle(cfg, 300.0, t as f32 / 32.0), time)
+            })
+            .collect::<Vec<_>>()
+    };
+    let cfg = config();
+    use SpinEvent::*;
+    assert_eq!(
+        detect_spins(&points(&cfg), &cfg),
+        vec![CwSpin(1), Cancelled(CancelReason::OppositeDirection)]
+    );
+    let cfg = GestureConfig {
+        keep_on_reversal: true,
+        ..config()
+    };
+    assert_eq!(
+        detect_spins(&points(&cfg), &cfg),
+        vec![CwSpin(1), CcwSpin(0)]
+    );
+}